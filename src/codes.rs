@@ -0,0 +1,223 @@
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use lazy_static::lazy_static;
+
+use crate::error::ErError;
+use crate::sync::SpinLock;
+
+/// The parity-check matrix of a (39,32) SEC-DAED code, which corrects any single flipped bit and
+/// any two flipped bits next to each other in a 32 bit word, with 7 check bits.
+///
+/// Each entry is a column of the matrix, with row `i` in bit `i`. The 32 data bits come first and
+/// the check bits last, as an identity matrix.
+pub const SEC_DAED_39_32: [u32; 39] = [
+    0x41, 0x5e, 0x47, 0x17, 0x25, 0x11, 0x0d, 0x3c, 0x46, 0x3e, 0x14, 0x7f, 0x28, 0x49, 0x65, 0x2f,
+    0x5a, 0x7b, 0x5d, 0x7e, 0x4d, 0x66, 0x74, 0x1a, 0x13, 0x58, 0x6d, 0x07, 0x5c, 0x1e, 0x05, 0x27,
+    0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40,
+];
+
+/// The id of `SEC_DAED_39_32`.
+pub const SEC_DAED_39_32_ID: u32 = 0;
+
+/// Codes that are built into the library as (parity-check matrix, number of data bits). They are
+/// registered in this order when the library is loaded, so their ids are their indices.
+const BUILTIN_CODES: [(&'static [u32], usize); 1] = [(&SEC_DAED_39_32, 32)];
+
+/// An error that the code corrects: its syndrome, and the bits it flipped in the data and in the
+/// check bits.
+struct Correction {
+    syndrome: u32,
+    data: u64,
+    check: u32,
+}
+
+/// A binary linear block code given by its parity-check matrix H, in systematic form.
+///
+/// Words of up to 64 data bits are protected by up to 32 check bits. The syndrome table is derived
+/// from H: every single flipped bit is corrected, and so are two flipped bits next to each other
+/// (both in the data or both in the check bits) if no other single or adjacent error has the same
+/// syndrome. Any other syndrome is detected, but cannot be corrected.
+pub struct LinearCode {
+    columns: Vec<u32>,
+    data_bits: usize,
+    check_bits: usize,
+    corrections: Vec<Correction>,
+}
+
+impl LinearCode {
+    /// Builds the code for the parity-check matrix with the given columns, of which the first
+    /// `data_bits` are for the data. The rest must be the identity matrix, so the check bits are
+    /// the syndrome of the data.
+    ///
+    /// Fails with `ErError::InvalidCode` if the data bits are not a whole number of bytes up to 64,
+    /// if there are more than 32 check bits, if the check columns are not the identity, if a data
+    /// column has a row past the check bits, or if two single flipped bits have the same syndrome.
+    pub fn new(columns: &[u32], data_bits: usize) -> Result<Self, ErError> {
+        if data_bits == 0 || data_bits > 64 || data_bits % 8 != 0 || columns.len() <= data_bits {
+            return Err(ErError::InvalidCode);
+        }
+        let check_bits = columns.len() - data_bits;
+        if check_bits > 32 {
+            return Err(ErError::InvalidCode);
+        }
+        let identity = columns[data_bits..]
+            .iter()
+            .enumerate()
+            .all(|(row, &column)| column == 1 << row);
+        if !identity {
+            return Err(ErError::InvalidCode);
+        }
+        // The data columns cannot have rows past the check bits
+        if columns[..data_bits].iter().any(|&column| (column as u64) >> check_bits != 0) {
+            return Err(ErError::InvalidCode);
+        }
+
+        // Every single error, then every adjacent double error, as (data, check) flips
+        let single = |bit: usize| match bit < data_bits {
+            true => (1u64 << bit, 0),
+            false => (0, 1u32 << (bit - data_bits)),
+        };
+        let mut candidates: Vec<(u64, u32)> = (0..columns.len()).map(single).collect();
+        let n_singles = candidates.len();
+        candidates.extend(
+            (1..columns.len())
+                .filter(|&bit| bit != data_bits)
+                .map(|bit| {
+                    let (first, second) = (single(bit - 1), single(bit));
+                    (first.0 | second.0, first.1 | second.1)
+                }),
+        );
+
+        let code = LinearCode {
+            columns: columns.to_vec(),
+            data_bits,
+            check_bits,
+            corrections: Vec::new(),
+        };
+        let syndromes: Vec<u32> = candidates
+            .iter()
+            .map(|&(data, check)| code.encode(data) ^ check)
+            .collect();
+        let singles = &syndromes[..n_singles];
+        let distinct = singles
+            .iter()
+            .enumerate()
+            .all(|(idx, &s)| s != 0 && !singles[..idx].contains(&s));
+        if !distinct {
+            return Err(ErError::InvalidCode);
+        }
+        let unique = |idx: usize| {
+            syndromes[idx] != 0
+                && syndromes.iter().enumerate().all(|(other, &s)| other == idx || s != syndromes[idx])
+        };
+
+        let corrections = (0..candidates.len())
+            .filter(|&idx| idx < n_singles || unique(idx))
+            .map(|idx| Correction {
+                syndrome: syndromes[idx],
+                data: candidates[idx].0,
+                check: candidates[idx].1,
+            })
+            .collect();
+        Ok(LinearCode { corrections, ..code })
+    }
+
+    /// The number of bytes of data in each word.
+    pub fn data_bytes(&self) -> usize {
+        self.data_bits / 8
+    }
+
+    /// The number of bytes the check bits of each word are stored in.
+    pub fn check_bytes(&self) -> usize {
+        (self.check_bits + 7) / 8
+    }
+
+    /// The bits of the stored check bytes that hold check bits. The others are ignored.
+    pub fn check_mask(&self) -> u32 {
+        match self.check_bits {
+            32 => !0,
+            bits => (1 << bits) - 1,
+        }
+    }
+
+    /// The check bits of a word of data, which are the XOR of the columns of its set bits.
+    pub fn encode(&self, word: u64) -> u32 {
+        self.columns[..self.data_bits]
+            .iter()
+            .enumerate()
+            .filter(|(bit, _)| (word >> bit) & 1 == 1)
+            .fold(0, |acc, (_, column)| acc ^ column)
+    }
+
+    /// Corrects a word of data and its check bits in place. Returns the number of flipped bits,
+    /// or `None` if the syndrome is not one the code can correct.
+    pub fn correct(&self, word: &mut u64, check: &mut u32) -> Option<u32> {
+        let syndrome = self.encode(*word) ^ *check;
+        if syndrome == 0 {
+            return Some(0);
+        }
+        let correction = self.corrections.iter().find(|c| c.syndrome == syndrome)?;
+        *word ^= correction.data;
+        *check ^= correction.check;
+        Some(correction.data.count_ones() + correction.check.count_ones())
+    }
+}
+
+lazy_static! {
+    static ref CODES: SpinLock<Vec<&'static LinearCode>> = SpinLock::new(
+        BUILTIN_CODES
+            .iter()
+            .map(|&(columns, data_bits)| {
+                let code = LinearCode::new(columns, data_bits).expect("LinearCode: Invalid built in code");
+                &*Box::leak(Box::new(code))
+            })
+            .collect()
+    );
+}
+
+/// Makes a code available to `Policy::LinearCode` and returns its id. Codes are kept for the
+/// lifetime of the process, since blocks may refer to them at any time.
+pub fn register(code: LinearCode) -> u32 {
+    let mut codes = CODES.lock();
+    codes.push(Box::leak(Box::new(code)));
+    (codes.len() - 1) as u32
+}
+
+/// The code with the given id, if one was registered.
+pub fn get(id: u32) -> Option<&'static LinearCode> {
+    CODES.lock().get(id as usize).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sec_daed_check() {
+        let code = get(SEC_DAED_39_32_ID).unwrap();
+        // Every single and adjacent double error has its own syndrome
+        assert_eq!(code.corrections.len(), 39 + 31 + 6);
+
+        let data = 0xdead_beef;
+        let check = code.encode(data);
+        for bit in 0..31 {
+            let (mut word, mut stored) = (data ^ (0b11 << bit), check);
+            assert_eq!(code.correct(&mut word, &mut stored), Some(2));
+            assert_eq!((word, stored), (data, check));
+        }
+        let (mut word, mut stored) = (data, check ^ 0b1000);
+        assert_eq!(code.correct(&mut word, &mut stored), Some(1));
+        assert_eq!((word, stored), (data, check));
+
+        // The check columns must be the identity, and single errors must be told apart
+        assert!(LinearCode::new(&[0x3, 0x5, 0x6, 0x7, 0x9, 0xa, 0xb, 0xc, 0x1, 0x2, 0x8, 0x4], 8).is_err());
+        assert!(LinearCode::new(&[0x3, 0x3, 0x5, 0x6, 0x7, 0x9, 0xa, 0xb, 0x1, 0x2, 0x4, 0x8], 8).is_err());
+
+        // A data column with a row that has no check bit would be lost from the syndrome
+        assert!(LinearCode::new(&[0x3, 0x5, 0x6, 0x7, 0x9, 0xa, 0xb, 0x1c, 0x1, 0x2, 0x4, 0x8], 8).is_err());
+        assert!(LinearCode::new(&[0x3, 0x5, 0x6, 0x7, 0x9, 0xa, 0xb, 0xc, 0x1, 0x2, 0x4, 0x8], 8).is_ok());
+    }
+}
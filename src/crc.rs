@@ -0,0 +1,40 @@
+/// CRC-32 (IEEE 802.3, reflected, polynomial 0xEDB88320), as used by zlib.
+///
+/// This is computed a bit at a time rather than with a lookup table, since a table would itself
+/// be exposed to the bit flips the checksum is meant to catch.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+/// CRC-64/XZ (ECMA-182 polynomial, reflected as 0xC96C5795D7870F42), computed the same way.
+pub fn crc64(data: &[u8]) -> u64 {
+    let mut crc = !0u64;
+    for &byte in data {
+        crc ^= byte as u64;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xC96C_5795_D787_0F42 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_values() {
+        // The standard check values of each CRC
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc64(b"123456789"), 0x995D_C9BB_DF19_39FA);
+    }
+}
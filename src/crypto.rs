@@ -0,0 +1,206 @@
+extern crate alloc;
+extern crate core;
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::ffi::CStr;
+
+use libc;
+
+use lazy_static::lazy_static;
+
+use aes_ctr::stream_cipher::generic_array::GenericArray;
+use aes_ctr::stream_cipher::{NewStreamCipher, SyncStreamCipher};
+use aes_ctr::Aes128Ctr;
+
+use aes_gcm::aead::NewAead;
+use aes_gcm::Aes128Gcm;
+
+use crate::error::ErError;
+use crate::keys::*;
+use crate::sync::SpinLock;
+use crate::zeroize::{self, Wiped};
+
+// AES-CTR mode with 128 bit key and 128 bit nonce
+pub const KEY_LEN: usize = 16;
+pub const NONCE_LEN: usize = 16;
+
+// AES-GCM with the same key, a 96 bit nonce and a 128 bit tag
+pub const AEAD_NONCE_LEN: usize = 12;
+pub const TAG_LEN: usize = 16;
+
+// Every block stores the id of the key it is encrypted under next to its nonce
+pub const KEY_ID_LEN: usize = 4;
+
+// Label that separates derived block keys from any other use of the master key
+const BLOCK_KEY_LABEL: &'static [u8; 4] = b"erbk";
+
+// Setting this environment variable makes the first key come from the named file
+const KEY_FILE_ENV: &'static [u8] = b"ERMALLOC_KEY_FILE\0";
+
+/// The providers of the keys that blocks may be encrypted under.
+///
+/// While keys are being rotated, blocks are moved from the previous keys to the current one
+/// one at a time, so the providers being replaced are kept until a rotation moves every block.
+/// A rotation that fails part way leaves blocks under older keys, so all of them are kept.
+struct KeyRing {
+    current: Box<dyn KeyProvider>,
+    current_id: u32,
+    previous: Vec<(Box<dyn KeyProvider>, u32)>,
+}
+
+impl KeyRing {
+    fn get(&mut self, id: u32) -> Result<[u8; KEY_LEN], ErError> {
+        let mut key = [0u8; KEY_LEN];
+        if id == self.current_id {
+            self.current.key(&mut key)?;
+            return Ok(key);
+        }
+        match self.previous.iter_mut().find(|(_, previous_id)| *previous_id == id) {
+            Some((provider, _)) => {
+                provider.key(&mut key)?;
+                Ok(key)
+            }
+            None => Err(ErError::UnknownKey),
+        }
+    }
+}
+
+/// The provider of the first key: the file named by `ERMALLOC_KEY_FILE` if it is set,
+/// and otherwise a random key from the kernel that never leaves the process.
+///
+/// This runs when the library is loaded, so a key file that cannot be read does not abort the
+/// process. Its error is returned by every encryption instead, until another key is set.
+fn default_provider() -> Box<dyn KeyProvider> {
+    let path = unsafe { libc::getenv(KEY_FILE_ENV.as_ptr() as *const libc::c_char) };
+    if path.is_null() {
+        return Box::new(MemoryKeyProvider::random());
+    }
+    match FileKeyProvider::open(unsafe { CStr::from_ptr(path) }) {
+        Ok(provider) => Box::new(provider),
+        Err(e) => Box::new(UnavailableKeyProvider::new(e)),
+    }
+}
+
+lazy_static! {
+    static ref KEYS: SpinLock<KeyRing> = SpinLock::new(KeyRing {
+        current: default_provider(),
+        current_id: 0,
+        previous: Vec::new(),
+    });
+}
+
+/// Sets up the key when the library is loaded, so the first encryption does not pay for it.
+#[used]
+#[link_section = ".init_array"]
+static INIT_KEY: extern "C" fn() = init_key;
+
+extern "C" fn init_key() {
+    lazy_static::initialize(&KEYS);
+}
+
+/// Fills `buf` with random bytes from the kernel.
+///
+/// This uses the `getrandom` syscall directly since there is no std to provide an rng.
+pub fn fill_random(buf: &mut [u8]) {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let remaining = &mut buf[filled..];
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_getrandom,
+                remaining.as_mut_ptr() as *mut libc::c_void,
+                remaining.len(),
+                0,
+            )
+        };
+        if ret < 0 {
+            if unsafe { *libc::__errno_location() } == libc::EINTR {
+                continue;
+            }
+            panic!("getrandom: Unable to get random bytes from the kernel");
+        }
+        filled += ret as usize;
+    }
+}
+
+/// Generates a fresh nonce. A new nonce is used on every write so no two writes share a keystream.
+pub fn new_nonce() -> [u8; NONCE_LEN] {
+    let mut nonce = [0u8; NONCE_LEN];
+    fill_random(&mut nonce);
+    nonce
+}
+
+/// Same as `new_nonce`, but sized for the authenticated cipher.
+pub fn new_aead_nonce() -> [u8; AEAD_NONCE_LEN] {
+    let mut nonce = [0u8; AEAD_NONCE_LEN];
+    fill_random(&mut nonce);
+    nonce
+}
+
+/// The id of the key that new writes are encrypted under.
+pub fn current_key_id() -> u32 {
+    KEYS.lock().current_id
+}
+
+/// Makes the key from `provider` the current key and returns its id. The provider it replaces
+/// is kept so blocks can still be decrypted until they are rotated.
+pub fn install_provider(provider: Box<dyn KeyProvider>) -> u32 {
+    let mut keys = KEYS.lock();
+    let previous_id = keys.current_id;
+    let previous = core::mem::replace(&mut keys.current, provider);
+    keys.previous.push((previous, previous_id));
+    keys.current_id = previous_id.wrapping_add(1);
+    keys.current_id
+}
+
+/// Forgets the previous keys once no block is encrypted under any of them anymore. Only the
+/// rotation that installed the current key may call it, see `set_key_provider`.
+pub fn retire_previous_keys() {
+    KEYS.lock().previous.clear();
+}
+
+/// Derives the key of a block from the master key, so blocks never share a keystream even if
+/// their nonces collide.
+///
+/// This is a counter mode KDF (NIST SP 800-108) with AES under the master key as the PRF. The PRF
+/// input is `label || counter || block_id`, and one output block is exactly one key. AES-CTR with
+/// that input as its initial counter block computes `AES(master, input)` as the keystream for
+/// a zero block.
+fn derive_block_key(master: &[u8; KEY_LEN], block_id: u64) -> [u8; KEY_LEN] {
+    let mut input = [0u8; NONCE_LEN];
+    input[..4].copy_from_slice(BLOCK_KEY_LABEL);
+    input[4..8].copy_from_slice(&1u32.to_be_bytes());
+    input[8..].copy_from_slice(&block_id.to_be_bytes());
+
+    let mut key = [0u8; KEY_LEN];
+    Wiped::new(Aes128Ctr::new(GenericArray::from_slice(master), GenericArray::from_slice(&input)))
+        .apply_keystream(&mut key);
+    key
+}
+
+/// The key of the given block under the master key with the given id.
+fn block_key(key_id: u32, block_id: u64) -> Result<[u8; KEY_LEN], ErError> {
+    let mut master = KEYS.lock().get(key_id)?;
+    let key = derive_block_key(&master, block_id);
+    zeroize::wipe(&mut master);
+    Ok(key)
+}
+
+/// Constructs the cipher for the given nonce under the given block's key, derived from the
+/// master key with the given id. Its round keys are wiped when it is dropped.
+pub fn cipher(key_id: u32, block_id: u64, nonce: &[u8]) -> Result<Wiped<Aes128Ctr>, ErError> {
+    let mut key = block_key(key_id, block_id)?;
+    let cipher = Wiped::new(Aes128Ctr::new(GenericArray::from_slice(&key), GenericArray::from_slice(nonce)));
+    zeroize::wipe(&mut key);
+    Ok(cipher)
+}
+
+/// Constructs the authenticated cipher under the given block's key. Its round keys and hash
+/// key are wiped when it is dropped.
+pub fn aead(key_id: u32, block_id: u64) -> Result<Wiped<Aes128Gcm>, ErError> {
+    let mut key = block_key(key_id, block_id)?;
+    let aead = Wiped::new(Aes128Gcm::new(aes_gcm::aead::generic_array::GenericArray::clone_from_slice(&key)));
+    zeroize::wipe(&mut key);
+    Ok(aead)
+}
@@ -0,0 +1,56 @@
+extern crate core;
+
+use core::fmt;
+
+use libc::c_int;
+
+/// Unrecoverable errors found while enforcing the policies on a block.
+///
+/// These are returned to C callers as negative values so they can be told apart from a count of
+/// corrected errors. The values must match `enum er_error` in `ermalloc.h`.
+#[repr(i32)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ErError {
+    /// The authentication tag did not match the data after error correction, so the data was either
+    /// tampered with or has more errors than the policies could correct.
+    TagMismatch = -1,
+    /// The data is encrypted under a key that has since been retired.
+    UnknownKey = -2,
+    /// A key of the wrong length was supplied.
+    InvalidKey = -3,
+    /// The key provider could not supply the key.
+    KeyUnavailable = -4,
+    /// Too many copies of the stored key were corrupted to recover it.
+    UnrecoverableKey = -5,
+    /// Locked memory could not be allocated, usually because the mlock limit was reached.
+    LockFailed = -6,
+    /// The data has more errors than its policies can correct.
+    Uncorrectable = -7,
+    /// A parity-check matrix does not describe a code that can correct single errors.
+    InvalidCode = -8,
+    /// The memory for a block could not be allocated.
+    AllocFailed = -9,
+    /// The metadata of a block and its backup were both corrupted, so the block cannot be used.
+    CorruptHeader = -10,
+    /// The pointer was not returned by the allocator, or points into the middle of a block.
+    InvalidPointer = -11,
+    /// The block was already freed: a double free, or a use after free.
+    AlreadyFreed = -12,
+    /// Links in the registry of live blocks were corrupted beyond repair, so some blocks could
+    /// not be reached.
+    CorruptRegistry = -13,
+    /// A thread could not be started.
+    ThreadFailed = -14,
+}
+
+impl ErError {
+    pub fn code(self) -> c_int {
+        self as c_int
+    }
+}
+
+impl fmt::Display for ErError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
@@ -1,13 +1,25 @@
+extern crate alloc;
 extern crate core;
 
 use libc::*;
 
+use alloc::boxed::Box;
+use core::ffi::CStr;
 use core::ptr;
 use core::convert::TryFrom;
 use core::fmt;
 use core::slice;
 
 use crate::policies::*;
+use crate::codes::{self, LinearCode};
+use crate::crypto::KEY_LEN;
+use crate::error::ErError;
+use crate::keys::*;
+use crate::locked::{self, LockMode};
+use crate::rs::{self, RsParams};
+use crate::scrub::{self, ScrubStats};
+use crate::vote::{ElementType, TypedVote, VoteMode};
+use crate::zeroize;
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
@@ -16,6 +28,18 @@ pub enum ErPolicyRaw {
     Redundancy,
     ReedSolomon,
     Encrypted,
+    Authenticated,
+    SecDed,
+    Checksum,
+    Interleaved,
+    UnequalProtection,
+    DataOnlyReedSolomon,
+    DataOnlySecDed,
+    LinearCode,
+    ReedSolomonField,
+    CheckedRedundancy,
+    TypedRedundancy,
+    SeparatedRedundancy,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -23,6 +47,71 @@ pub enum FfiError {
     PolicyValueUnknown,
     PolicyDataWasNull,
     MoreThanMaxPolicies,
+    ChecksumWidthUnknown,
+    WordWidthUnknown,
+    CodeUnknown,
+    FieldInvalid,
+    CopiesOutOfRange,
+}
+
+/// The policy data of `UnequalProtection`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct ErSignificanceRaw {
+    width: u32,
+    mask: u64,
+}
+
+/// The policy data of `ReedSolomonField`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct ErReedSolomonFieldRaw {
+    n_ecc: u32,
+    params: RsParams,
+}
+
+/// The policy data of `TypedRedundancy`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct ErTypedVoteRaw {
+    n_copies: u32,
+    element: c_int,
+    mode: c_int,
+    epsilon: f64,
+}
+
+impl ErTypedVoteRaw {
+    /// The number of copies and the vote asked for, or `None` if the element type or the mode is
+    /// not one of the values of its enum, there are no copies, or epsilon is not a number of at
+    /// least 0.
+    fn vote(&self) -> Option<(u32, TypedVote)> {
+        let element = match self.element {
+            0 => ElementType::U16,
+            1 => ElementType::U32,
+            2 => ElementType::U64,
+            3 => ElementType::F32,
+            4 => ElementType::F64,
+            _ => return None
+        };
+        let mode = match self.mode {
+            0 => VoteMode::Majority,
+            1 => VoteMode::Median,
+            2 => VoteMode::Tolerance,
+            _ => return None
+        };
+        if self.n_copies < 1 || self.epsilon.is_nan() || self.epsilon < 0.0 {
+            return None;
+        }
+        Some((self.n_copies, TypedVote { element, mode, epsilon: self.epsilon }))
+    }
+}
+
+/// The policy data of `SeparatedRedundancy`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct ErSeparationRaw {
+    n_copies: u32,
+    guard_pages: u32,
 }
 
 impl fmt::Display for FfiError {
@@ -126,7 +215,127 @@ impl From<ErPolicyListNonNull> for Policy {
                 }
                 Policy::ReedSolomon(num)
             },
-            ErPolicyRaw::Encrypted => Policy::Encrypted
+            ErPolicyRaw::Encrypted => Policy::Encrypted,
+            ErPolicyRaw::Authenticated => Policy::Authenticated,
+            ErPolicyRaw::SecDed => Policy::SecDed,
+            ErPolicyRaw::DataOnlySecDed => Policy::DataOnlySecDed,
+            ErPolicyRaw::DataOnlyReedSolomon => {
+                let num = match raw.policy_data {
+                    Some(data) => unsafe { *data.cast::<u32>().as_ptr() },
+                    None => default_rs(),
+                };
+                Policy::DataOnlyReedSolomon(num)
+            },
+            ErPolicyRaw::Checksum => {
+                let width = match raw.policy_data {
+                    Some(data) => unsafe { *data.cast::<u32>().as_ptr() },
+                    None => default_checksum_width(),
+                };
+                if width != 32 && width != 64 {
+                    panic!("{}", FfiError::ChecksumWidthUnknown);
+                }
+                Policy::Checksum(width)
+            },
+            // Interleaving applies to the parity of a ReedSolomon policy in the same list,
+            // which is merged in by `setup_policy_helper`
+            ErPolicyRaw::Interleaved => {
+                let depth = match raw.policy_data {
+                    Some(data) => unsafe { *data.cast::<u32>().as_ptr() },
+                    None => default_interleave_depth(),
+                };
+                Policy::InterleavedReedSolomon(default_rs(), depth)
+            },
+            ErPolicyRaw::UnequalProtection => {
+                let significance = match raw.policy_data {
+                    Some(data) => unsafe { *data.cast::<ErSignificanceRaw>().as_ptr() },
+                    None => panic!("{}", FfiError::PolicyDataWasNull),
+                };
+                match significance.width {
+                    8 | 16 | 32 | 64 => (),
+                    _ => panic!("{}", FfiError::WordWidthUnknown),
+                }
+                Policy::UnequalProtection(significance.width, significance.mask)
+            },
+            ErPolicyRaw::LinearCode => {
+                let id = match raw.policy_data {
+                    Some(data) => unsafe { *data.cast::<u32>().as_ptr() },
+                    None => panic!("{}", FfiError::PolicyDataWasNull),
+                };
+                if codes::get(id).is_none() {
+                    panic!("{}", FfiError::CodeUnknown);
+                }
+                Policy::LinearCode(id)
+            },
+            ErPolicyRaw::ReedSolomonField => {
+                let field = match raw.policy_data {
+                    Some(data) => unsafe { *data.cast::<ErReedSolomonFieldRaw>().as_ptr() },
+                    None => panic!("{}", FfiError::PolicyDataWasNull),
+                };
+                if rs::codec(field.n_ecc as usize, &field.params).is_none() {
+                    panic!("{}", FfiError::FieldInvalid);
+                }
+                Policy::ReedSolomonField(field.n_ecc, field.params)
+            },
+            ErPolicyRaw::CheckedRedundancy => {
+                let num = match raw.policy_data {
+                    Some(data) => unsafe { *data.cast::<u32>().as_ptr() },
+                    None => default_redundancy(),
+                };
+                Policy::CheckedRedundancy(num)
+            },
+            ErPolicyRaw::TypedRedundancy => {
+                let raw_vote = match raw.policy_data {
+                    Some(data) => unsafe { *data.cast::<ErTypedVoteRaw>().as_ptr() },
+                    None => panic!("{}", FfiError::PolicyDataWasNull),
+                };
+                // Checked by setup_policy_helper before it gets here
+                let (n_copies, vote) = raw_vote.vote().expect("typed vote");
+                Policy::TypedRedundancy(n_copies, vote)
+            },
+            ErPolicyRaw::SeparatedRedundancy => {
+                let separation = match raw.policy_data {
+                    Some(data) => unsafe { *data.cast::<ErSeparationRaw>().as_ptr() },
+                    None => ErSeparationRaw {
+                        n_copies: default_redundancy(),
+                        guard_pages: default_guard_pages(),
+                    },
+                };
+                if separation.n_copies == 0 || separation.n_copies as usize > MAX_SEPARATED_COPIES {
+                    panic!("{}", FfiError::CopiesOutOfRange);
+                }
+                Policy::SeparatedRedundancy(separation.n_copies, separation.guard_pages)
+            },
+        }
+    }
+}
+
+impl ErPolicyListNonNull {
+    /// The memory an encryption policy asked for, which is its policy data if any, or `None`
+    /// if the policy data is not one of the values of `enum er_lock_mode`.
+    fn lock_mode(&self) -> Option<LockMode> {
+        match (self.policy, self.policy_data) {
+            (ErPolicyRaw::Encrypted, Some(data)) | (ErPolicyRaw::Authenticated, Some(data)) => {
+                match unsafe { *data.cast::<c_int>().as_ptr() } {
+                    0 => Some(LockMode::Default),
+                    1 => Some(LockMode::Locked),
+                    2 => Some(LockMode::Unlocked),
+                    _ => None
+                }
+            },
+            _ => Some(LockMode::Default)
+        }
+    }
+
+    /// Whether the policy data holds values that can be used. Only the policy data that
+    /// holds enums from C is checked here, rather than trusted. A typed vote has no default,
+    /// so its policy data is required.
+    fn data_is_valid(&self) -> bool {
+        match (self.policy, self.policy_data) {
+            (ErPolicyRaw::TypedRedundancy, Some(data)) => {
+                unsafe { *data.cast::<ErTypedVoteRaw>().as_ptr() }.vote().is_some()
+            },
+            (ErPolicyRaw::TypedRedundancy, None) => false,
+            _ => true
         }
     }
 }
@@ -144,10 +353,10 @@ impl TryFrom<ErPolicyListRaw> for ErPolicyListNonNull {
             }
         }
         match raw.policy {
-            ErPolicyRaw::Nil | ErPolicyRaw::Encrypted => {
+            ErPolicyRaw::Nil | ErPolicyRaw::SecDed | ErPolicyRaw::DataOnlySecDed => {
                 Ok(ErPolicyListNonNull::new(raw.policy, None, next))
             },
-            ErPolicyRaw::Redundancy => {
+            ErPolicyRaw::Redundancy | ErPolicyRaw::Encrypted | ErPolicyRaw::Authenticated | ErPolicyRaw::Checksum | ErPolicyRaw::Interleaved | ErPolicyRaw::UnequalProtection | ErPolicyRaw::DataOnlyReedSolomon | ErPolicyRaw::LinearCode | ErPolicyRaw::ReedSolomonField | ErPolicyRaw::CheckedRedundancy | ErPolicyRaw::TypedRedundancy | ErPolicyRaw::SeparatedRedundancy => {
                 if raw.policy_data.is_null() {
                     let policy_data = None;
                     Ok(ErPolicyListNonNull::new(raw.policy, policy_data, next))
@@ -183,29 +392,94 @@ fn default_rs() -> u32 {
     3
 }
 
-fn setup_policy_helper(size: size_t, policies: *const ErPolicyListRaw) -> Option<[Policy; MAX_POLICIES]> {
+fn default_checksum_width() -> u32 {
+    32
+}
+
+fn default_interleave_depth() -> u32 {
+    8
+}
+
+fn default_guard_pages() -> u32 {
+    1
+}
+
+/// Which code over the data is used when `new` is given after `current`: the one given last.
+/// A Reed-Solomon code and a word code (SecDed or LinearCode) cannot be layered, so a list with
+/// both gives `None`.
+fn combine_codes(current: Policy, new: Policy) -> Option<Policy> {
+    let is_rs = |pol: &Policy| matches!(pol,
+        Policy::ReedSolomon(_) | Policy::InterleavedReedSolomon(..) | Policy::DataOnlyReedSolomon(_) |
+        Policy::ReedSolomonField(..));
+    let is_word = |pol: &Policy| matches!(pol, Policy::SecDed | Policy::DataOnlySecDed | Policy::LinearCode(_));
+    match (current, new) {
+        // Interleaved keeps the parity of ReedSolomon, whichever is listed first
+        (Policy::ReedSolomon(n_ecc), Policy::InterleavedReedSolomon(_, depth)) |
+        (Policy::InterleavedReedSolomon(_, depth), Policy::ReedSolomon(n_ecc)) => {
+            Some(Policy::InterleavedReedSolomon(n_ecc, depth))
+        },
+        _ if (is_rs(&current) && is_word(&new)) || (is_word(&current) && is_rs(&new)) => None,
+        _ => Some(new),
+    }
+}
+
+fn setup_policy_helper(size: size_t, policies: *const ErPolicyListRaw) -> Option<([Policy; MAX_POLICIES], LockMode)> {
     if size == 0 {
         return None;
     }
 
     let mut policy_arr = [Policy::Nil; MAX_POLICIES];
     let mut policy_arr_ordered = [Policy::Nil; MAX_POLICIES];
+    let mut lock = LockMode::Default;
+    let mut significance = None;
     if policies != ptr::null() {
         let mut head = ErPolicyListNonNull::try_from(unsafe { *policies }).expect("policy list generation error");
+        // Interleaved shares the slot of ReedSolomon, so it does not count
+        let mut n_interleaved = 0;
         for i in 0.. {
-            if i >= MAX_POLICIES {
+            if matches!(head.policy, ErPolicyRaw::Interleaved) {
+                n_interleaved += 1;
+            }
+            if i >= MAX_POLICIES + n_interleaved {
                 panic!("{}", FfiError::MoreThanMaxPolicies);
             }
+            if !head.data_is_valid() {
+                unsafe { *__errno_location() = EINVAL };
+                return None;
+            }
             let pol = Policy::from(head);
             match pol {
-                Policy::Redundancy(_) => {
+                Policy::Redundancy(_) | Policy::CheckedRedundancy(_) | Policy::TypedRedundancy(..) |
+                Policy::SeparatedRedundancy(..) => {
                     policy_arr[0] = pol;
                 }
-                Policy::ReedSolomon(_) => {
+                // Checked after the code over the data has corrected it
+                Policy::Checksum(_) => {
                     policy_arr[1] = pol;
                 }
-                Policy::Encrypted => {
-                    policy_arr[2] = pol;
+                // These are all codes over the data, so only one of them is used
+                Policy::ReedSolomon(_) | Policy::InterleavedReedSolomon(..) | Policy::DataOnlyReedSolomon(_) |
+                Policy::ReedSolomonField(..) | Policy::SecDed | Policy::DataOnlySecDed | Policy::LinearCode(_) => {
+                    policy_arr[2] = match combine_codes(policy_arr[2], pol) {
+                        Some(pol) => pol,
+                        None => {
+                            unsafe { *__errno_location() = EINVAL };
+                            return None;
+                        }
+                    };
+                }
+                Policy::Encrypted | Policy::Authenticated => {
+                    policy_arr[3] = pol;
+                    lock = match head.lock_mode() {
+                        Some(mode) => mode,
+                        None => {
+                            unsafe { *__errno_location() = EINVAL };
+                            return None;
+                        }
+                    };
+                }
+                Policy::UnequalProtection(..) => {
+                    significance = Some(pol);
                 }
                 _ => (),
             }
@@ -215,7 +489,19 @@ fn setup_policy_helper(size: size_t, policies: *const ErPolicyListRaw) -> Option
             };
         }
 
-        // order the policies Redundancy -> ReedSol -> Encrypt
+        // Which bits are significant is lost once the data is encrypted, so unequal protection
+        // takes the innermost slot instead of encryption, and cannot be combined with it
+        if let Some(pol) = significance {
+            match policy_arr[3] {
+                Policy::Nil => policy_arr[3] = pol,
+                _ => {
+                    unsafe { *__errno_location() = EINVAL };
+                    return None;
+                }
+            }
+        }
+
+        // order the policies Redundancy -> Checksum -> ReedSol -> Encrypt
         let mut idx = 0;
         for pol in policy_arr.iter() {
             match pol {
@@ -228,20 +514,48 @@ fn setup_policy_helper(size: size_t, policies: *const ErPolicyListRaw) -> Option
         }
     }
 
-    Some(policy_arr)
+    Some((policy_arr_ordered, lock))
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn er_malloc(size: size_t, policies: *const ErPolicyListRaw) -> *mut c_void {
     match setup_policy_helper(size, policies) {
-        Some(policy_arr) => AllocBlock::new(size, &policy_arr, false).as_ptr().add(1) as *mut c_void,
+        Some((policy_arr, lock)) => match AllocBlock::new_in(size, &policy_arr, false, lock) {
+            Ok(w) => w.as_ptr().add(1) as *mut c_void,
+            Err(_) => ptr::null::<c_void>() as *mut c_void
+        },
         None => ptr::null::<c_void>() as *mut c_void
     }
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn er_free(ptr: *const c_void)  {
-    AllocBlock::drop(AllocBlock::from_usr_ptr_mut(ptr as *mut u8));
+pub unsafe extern "C" fn er_free(ptr: *const c_void) -> c_int {
+    if ptr.is_null() {
+        return 0;
+    }
+    let guard = match AllocBlock::lock_usr_ptr(ptr as *const u8) {
+        Ok(g) => g,
+        Err(e) => return e.code()
+    };
+    // A block whose header cannot be trusted is leaked, since its size is not known
+    match AllocBlock::from_usr_ptr_mut(ptr as *mut u8) {
+        Ok(w) => {
+            AllocBlock::drop(w);
+            guard.freed();
+            0
+        },
+        Err(e) => e.code()
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn er_wipe_all_on_free(_enabled: c_int) {
+    // Every block is poisoned when it is freed, which wipes it, so there is nothing to enable
+}
+
+#[no_mangle]
+pub extern "C" fn er_lock_encrypted(enabled: c_int) {
+    locked::set_lock_encrypted(enabled != 0);
 }
 
 #[no_mangle]
@@ -251,19 +565,40 @@ pub unsafe extern "C" fn er_calloc(nmemb: size_t, size: size_t, policies: *const
         None => return ptr::null::<c_void>() as *mut c_void
     };
     match setup_policy_helper(size, policies) {
-        Some(policy_arr) => AllocBlock::new(bytes, &policy_arr, true).as_ptr().add(1) as *mut c_void,
+        Some((policy_arr, lock)) => match AllocBlock::new_in(bytes, &policy_arr, true, lock) {
+            Ok(w) => w.as_ptr().add(1) as *mut c_void,
+            Err(_) => ptr::null::<c_void>() as *mut c_void
+        },
         None => ptr::null::<c_void>() as *mut c_void
     }
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn er_realloc(ptr: *const c_void, size: size_t, policies: *const ErPolicyListRaw) -> *mut c_void {
+    if ptr.is_null() {
+        return er_malloc(size, policies);
+    }
     if size == 0 {
         er_free(ptr);
         return ptr::null::<c_void>() as *mut c_void
     }
+    let guard = match AllocBlock::lock_usr_ptr(ptr as *const u8) {
+        Ok(g) => g,
+        Err(_) => return ptr::null::<c_void>() as *mut c_void
+    };
+    let w = match AllocBlock::from_usr_ptr_mut(ptr as *mut u8) {
+        Ok(w) => w,
+        Err(_) => return ptr::null::<c_void>() as *mut c_void
+    };
     match setup_policy_helper(size, policies) {
-        Some(policy_arr) => AllocBlock::renew(AllocBlock::from_usr_ptr_mut(ptr as *mut u8), size, &policy_arr).as_ptr().add(1) as *mut c_void,
+        Some((policy_arr, lock)) => match AllocBlock::renew(w, size, &policy_arr, lock) {
+            Ok(w) => {
+                guard.freed();
+                w.as_ptr().add(1) as *mut c_void
+            },
+            // The old block is left untouched, just like realloc
+            Err(_) => ptr::null::<c_void>() as *mut c_void
+        },
         None => ptr::null::<c_void>() as *mut c_void
     }
 }
@@ -277,48 +612,341 @@ pub unsafe extern "C" fn er_reallocarray(ptr: *const c_void, nmemb: size_t, size
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn er_setup_policies(ptr: *const c_void) {
-    let w = AllocBlock::from_usr_ptr_mut(ptr as *mut u8);
-    AllocBlock::apply_policy_ffi(w);
+pub unsafe extern "C" fn er_setup_policies(ptr: *const c_void) -> c_int {
+    let _guard = match AllocBlock::lock_usr_ptr(ptr as *const u8) {
+        Ok(g) => g,
+        Err(e) => return e.code()
+    };
+    setup_policies(ptr)
+}
+
+/// Same as `er_setup_policies`, for a block that is already locked.
+unsafe fn setup_policies(ptr: *const c_void) -> c_int {
+    let w = match AllocBlock::from_usr_ptr_mut(ptr as *mut u8) {
+        Ok(w) => w,
+        Err(e) => return e.code()
+    };
+    match AllocBlock::apply_policy_ffi(w) {
+        Ok(()) => 0,
+        Err(e) => e.code()
+    }
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn er_correct_buffer(ptr: *mut c_void) -> c_int {
-    let w = AllocBlock::from_usr_ptr_mut(ptr as *mut u8);
-    AllocBlock::correct_buffer_ffi(w) as c_int
+    let _guard = match AllocBlock::lock_usr_ptr(ptr as *const u8) {
+        Ok(g) => g,
+        Err(e) => return e.code()
+    };
+    correct_buffer(ptr)
+}
+
+/// Same as `er_correct_buffer`, for a block that is already locked.
+unsafe fn correct_buffer(ptr: *mut c_void) -> c_int {
+    let w = match AllocBlock::from_usr_ptr_mut(ptr as *mut u8) {
+        Ok(w) => w,
+        Err(e) => return e.code()
+    };
+    match AllocBlock::correct_buffer_ffi(w) {
+        Ok(c) => c as c_int,
+        Err(e) => e.code()
+    }
+}
+
+/// Collects per-codeword error counts into a caller supplied array.
+struct CodewordCounts<'a> {
+    counts: &'a mut [c_int],
+    n_codewords: usize,
+}
+
+impl<'a> CorrectionObserver for CodewordCounts<'a> {
+    fn codeword(&mut self, errors: Option<u32>) {
+        if let Some(count) = self.counts.get_mut(self.n_codewords) {
+            *count = match errors {
+                Some(n) => n as c_int,
+                None => -1,
+            };
+        }
+        self.n_codewords += 1;
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn er_codeword_errors(ptr: *mut c_void, counts: *mut c_int, max_counts: size_t) -> c_int {
+    let counts = if counts.is_null() {
+        &mut []
+    } else {
+        slice::from_raw_parts_mut(counts, max_counts)
+    };
+    let mut observer = CodewordCounts { counts, n_codewords: 0 };
+    let _guard = match AllocBlock::lock_usr_ptr(ptr as *const u8) {
+        Ok(g) => g,
+        Err(e) => return e.code()
+    };
+    let w = match AllocBlock::from_usr_ptr_mut(ptr as *mut u8) {
+        Ok(w) => w,
+        Err(e) => return e.code()
+    };
+    let _ = AllocBlock::correct_buffer_observed_ffi(w, &mut observer);
+    observer.n_codewords as c_int
+}
+
+/// Collects the copies that disagreed with the outcome of a typed vote.
+struct DisagreeingCopies<'a> {
+    copies: &'a mut [size_t],
+    n_copies: usize,
+}
+
+impl<'a> CorrectionObserver for DisagreeingCopies<'a> {
+    fn copies_disagreed(&mut self, copies: &[usize]) {
+        for (slot, &copy) in self.copies.iter_mut().zip(copies.iter()) {
+            *slot = copy;
+        }
+        self.n_copies = copies.len();
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn er_disagreeing_copies(ptr: *mut c_void, copies: *mut size_t, max_copies: size_t) -> c_int {
+    let copies = if copies.is_null() {
+        &mut []
+    } else {
+        slice::from_raw_parts_mut(copies, max_copies)
+    };
+    let mut observer = DisagreeingCopies { copies, n_copies: 0 };
+    let _guard = match AllocBlock::lock_usr_ptr(ptr as *const u8) {
+        Ok(g) => g,
+        Err(e) => return e.code()
+    };
+    let w = match AllocBlock::from_usr_ptr_mut(ptr as *mut u8) {
+        Ok(w) => w,
+        Err(e) => return e.code()
+    };
+    match AllocBlock::correct_buffer_observed_ffi(w, &mut observer) {
+        Ok(_) => observer.n_copies as c_int,
+        Err(e) => e.code()
+    }
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn er_read_buf(base: *mut c_void, dest: *mut c_void, offset: size_t, len: size_t) -> c_int {
-    let c = er_correct_buffer(base);
+    let _guard = match AllocBlock::lock_usr_ptr(base as *const u8) {
+        Ok(g) => g,
+        Err(e) => return e.code()
+    };
+    let c = correct_buffer(base);
     if c < 0 {
         return c;
     }
     
-    let w_decrypted = AllocBlock::from_usr_ptr_mut(base as *mut u8);
-    AllocBlock::decrypt_buffer_ffi(w_decrypted);
-
-    let w = AllocBlock::from_usr_ptr_mut(base as *mut u8);
-    let src_buf = AllocBlock::data_slice_ffi(w).split_at_mut(offset).1.split_at_mut(len).0;
+    // Only the requested bytes are decrypted, straight into dest
+    let w = match AllocBlock::from_usr_ptr_mut(base as *mut u8) {
+        Ok(w) => w,
+        Err(e) => return e.code()
+    };
     let dst_buf = slice::from_raw_parts_mut(dest as *mut u8, len);
-    dst_buf.copy_from_slice(src_buf);
-
-    let w_recrypt = AllocBlock::from_usr_ptr_mut(base as *mut u8);
-    AllocBlock::encrypt_buffer_ffi(w_recrypt);
-    c
+    match AllocBlock::read_ffi(w, dst_buf, offset) {
+        Ok(()) => c,
+        Err(e) => e.code()
+    }
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn er_write_buf(base: *mut c_void, src: *const c_void, offset: size_t, len: size_t) -> c_int {
-    let w = AllocBlock::from_usr_ptr_mut(base as *mut u8);
+    let _guard = match AllocBlock::lock_usr_ptr(base as *const u8) {
+        Ok(g) => g,
+        Err(e) => return e.code()
+    };
+    // When only part of the data is overwritten, the bytes around it are kept, so they
+    // must be corrected and decrypted before the policies are applied again
+    let w_len = match AllocBlock::from_usr_ptr(base as *const u8) {
+        Ok(w) => w,
+        Err(e) => return e.code()
+    };
+    let partial = offset != 0 || len != AllocBlock::length_ffi(w_len);
+    let mut c = 0;
+    if partial {
+        c = correct_buffer(base);
+        if c < 0 {
+            return c;
+        }
+    }
+
+    let w = match AllocBlock::from_usr_ptr_mut(base as *mut u8) {
+        Ok(w) => w,
+        Err(e) => return e.code()
+    };
     let dst_buf = AllocBlock::data_slice_ffi(w).split_at_mut(offset).1.split_at_mut(len).0;
     let src_buf = slice::from_raw_parts_mut(src as *mut u8, len);
 
-    let w_decrypted = AllocBlock::from_usr_ptr_mut(base as *mut u8);
-    AllocBlock::decrypt_buffer_ffi(w_decrypted);
+    if partial {
+        let w_decrypted = match AllocBlock::from_usr_ptr_mut(base as *mut u8) {
+            Ok(w) => w,
+            Err(e) => return e.code()
+        };
+        if let Err(e) = AllocBlock::decrypt_buffer_ffi(w_decrypted) {
+            return e.code();
+        }
+    }
 
     dst_buf.copy_from_slice(src_buf);
 
-    er_setup_policies(base);
-    0
+    let ret = setup_policies(base);
+    if ret < 0 {
+        return ret;
+    }
+    c
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn er_set_key(key: *const c_void, len: size_t) -> c_int {
+    if key.is_null() || len != KEY_LEN {
+        return ErError::InvalidKey.code();
+    }
+    let mut new_key = [0u8; KEY_LEN];
+    new_key.copy_from_slice(slice::from_raw_parts(key as *const u8, len));
+    let result = set_key(&new_key);
+    zeroize::wipe(&mut new_key);
+    match result {
+        Ok(rotated) => rotated as c_int,
+        Err(e) => e.code()
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn er_rotate_key() -> c_int {
+    match rotate_key() {
+        Ok(rotated) => rotated as c_int,
+        Err(e) => e.code()
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn er_register_key_provider(callback: Option<KeyCallback>, ctx: *mut c_void) -> c_int {
+    let callback = match callback {
+        Some(f) => f,
+        None => return ErError::InvalidKey.code()
+    };
+    match set_key_provider(Box::new(CallbackKeyProvider::new(callback, ctx))) {
+        Ok(rotated) => rotated as c_int,
+        Err(e) => e.code()
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn er_load_key_file(path: *const c_char) -> c_int {
+    if path.is_null() {
+        return ErError::KeyUnavailable.code();
+    }
+    let provider = match FileKeyProvider::open(CStr::from_ptr(path)) {
+        Ok(p) => p,
+        Err(e) => return e.code()
+    };
+    match set_key_provider(Box::new(provider)) {
+        Ok(rotated) => rotated as c_int,
+        Err(e) => e.code()
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn er_register_linear_code(columns: *const u32, n_columns: size_t, data_bits: size_t) -> c_int {
+    if columns.is_null() {
+        return ErError::InvalidCode.code();
+    }
+    match LinearCode::new(slice::from_raw_parts(columns, n_columns), data_bits) {
+        Ok(code) => codes::register(code) as c_int,
+        Err(e) => e.code()
+    }
+}
+
+/// A live block, as passed to the callback of `er_for_each_block`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct ErBlockInfoRaw {
+    ptr: *mut c_void,
+    length: size_t,
+    buffer_size: size_t,
+    id: u64,
+}
+
+pub type BlockCallback = unsafe extern "C" fn(info: *const ErBlockInfoRaw, ctx: *mut c_void) -> c_int;
+
+#[no_mangle]
+pub unsafe extern "C" fn er_for_each_block(callback: Option<BlockCallback>, ctx: *mut c_void) -> c_int {
+    let callback = match callback {
+        Some(f) => f,
+        None => return 0
+    };
+    let result = for_each_block(|info| {
+        let raw = ErBlockInfoRaw {
+            ptr: info.ptr as *mut c_void,
+            length: info.length,
+            buffer_size: info.buffer_size,
+            id: info.id,
+        };
+        callback(&raw, ctx) == 0
+    });
+    match result {
+        Ok(visited) => visited as c_int,
+        Err(e) => e.code()
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn er_scrubber_start(interval_ms: c_uint, bytes_per_tick: size_t) -> c_int {
+    match scrub::start(interval_ms, bytes_per_tick) {
+        Ok(()) => 0,
+        Err(e) => e.code()
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn er_scrubber_stop() {
+    scrub::stop();
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn er_scrubber_stats(stats: *mut ScrubStats) {
+    if !stats.is_null() {
+        *stats = scrub::stats();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encrypted_only_check() {
+        // Encrypted alone lands in the first slot, so the block does not stop before it
+        let list = ErPolicyListRaw::new(ErPolicyRaw::Encrypted, ptr::null(), ptr::null());
+        let (policies, _) = setup_policy_helper(32, &list).unwrap();
+        assert!(matches!(policies[0], Policy::Encrypted));
+
+        let plaintext = [0xabu8; 32];
+        let mut read = [0u8; 32];
+        unsafe {
+            let ptr = er_malloc(32, &list);
+            assert!(!ptr.is_null());
+            assert_eq!(er_write_buf(ptr, plaintext.as_ptr() as *const c_void, 0, 32), 0);
+            // What is stored is the ciphertext
+            assert_ne!(slice::from_raw_parts(ptr as *const u8, 32), &plaintext[..]);
+            assert!(er_read_buf(ptr, read.as_mut_ptr() as *mut c_void, 0, 32) >= 0);
+            assert_eq!(er_free(ptr), 0);
+        }
+        assert_eq!(read, plaintext);
+    }
+
+    #[test]
+    fn typed_vote_check() {
+        // A typed vote without its policy data is refused rather than aborting the process
+        let list = ErPolicyListRaw::new(ErPolicyRaw::TypedRedundancy, ptr::null(), ptr::null());
+        assert!(setup_policy_helper(16, &list).is_none());
+        assert_eq!(unsafe { *__errno_location() }, EINVAL);
+        assert!(unsafe { er_malloc(16, &list) }.is_null());
+
+        let vote = ErTypedVoteRaw { n_copies: 3, element: 1, mode: 0, epsilon: 0.0 };
+        let list = ErPolicyListRaw::new(ErPolicyRaw::TypedRedundancy, &vote as *const _ as *const c_void, ptr::null());
+        assert!(matches!(setup_policy_helper(16, &list), Some(([Policy::TypedRedundancy(3, _), ..], _))));
+    }
 }
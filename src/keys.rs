@@ -0,0 +1,239 @@
+extern crate core;
+
+use core::ffi::CStr;
+
+use libc::{c_int, c_void, size_t};
+
+use crate::crc::crc32;
+use crate::crypto::{self, KEY_LEN};
+use crate::error::ErError;
+use crate::zeroize;
+
+const KEY_COPIES: usize = 3;
+
+/// A key kept in memory with triple redundancy, each copy with its own checksum.
+///
+/// The key is voted and checked every time it is read, and the copies are repaired from the
+/// result, so bit flips do not accumulate. The expanded round keys are not stored at all: they are
+/// rebuilt from the checked key for every cipher and dropped once the buffer has been processed.
+pub struct HardenedKey {
+    copies: [[u8; KEY_LEN]; KEY_COPIES],
+    checksums: [u32; KEY_COPIES],
+}
+
+impl HardenedKey {
+    pub fn new(key: &[u8; KEY_LEN]) -> Self {
+        let checksum = crc32(key);
+        HardenedKey {
+            copies: [*key; KEY_COPIES],
+            checksums: [checksum; KEY_COPIES],
+        }
+    }
+
+    /// Recovers the key into `key`, repairing any corrupted copies.
+    ///
+    /// The bitwise vote of the copies is used if it matches one of the checksums. Otherwise
+    /// (two copies were hit at the same bit) any single copy that matches a checksum is used.
+    pub fn recover(&mut self, key: &mut [u8; KEY_LEN]) -> Result<(), ErError> {
+        for idx in 0..KEY_LEN {
+            let [a, b, c] = [self.copies[0][idx], self.copies[1][idx], self.copies[2][idx]];
+            key[idx] = (a & b) | (a & c) | (b & c);
+        }
+
+        let is_valid = |k: &[u8; KEY_LEN]| {
+            let crc = crc32(k);
+            self.checksums.iter().any(|&checksum| checksum == crc)
+        };
+        if !is_valid(key) {
+            match self.copies.iter().find(|copy| is_valid(copy)) {
+                Some(copy) => key.copy_from_slice(copy),
+                None => return Err(ErError::UnrecoverableKey),
+            }
+        }
+
+        let checksum = crc32(key);
+        for (copy, stored) in self.copies.iter_mut().zip(self.checksums.iter_mut()) {
+            copy.copy_from_slice(key);
+            *stored = checksum;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for HardenedKey {
+    fn drop(&mut self) {
+        for copy in self.copies.iter_mut() {
+            zeroize::wipe(copy);
+        }
+    }
+}
+
+/// A source of encryption keys.
+///
+/// The library asks the provider for the key every time it builds a cipher rather than keeping
+/// its own copy, so keys can live wherever the provider keeps them (in memory, in a file, or
+/// behind a hardware security module).
+pub trait KeyProvider: Send {
+    /// Writes the key into `key`.
+    fn key(&mut self, key: &mut [u8; KEY_LEN]) -> Result<(), ErError>;
+}
+
+/// Keeps the key in process memory. This is the default, with a key from the kernel.
+pub struct MemoryKeyProvider {
+    key: HardenedKey,
+}
+
+impl MemoryKeyProvider {
+    pub fn new(key: &[u8; KEY_LEN]) -> Self {
+        MemoryKeyProvider { key: HardenedKey::new(key) }
+    }
+
+    /// A provider for a fresh random key.
+    pub fn random() -> Self {
+        let mut key = [0u8; KEY_LEN];
+        crypto::fill_random(&mut key);
+        let provider = MemoryKeyProvider::new(&key);
+        zeroize::wipe(&mut key);
+        provider
+    }
+}
+
+impl KeyProvider for MemoryKeyProvider {
+    fn key(&mut self, key: &mut [u8; KEY_LEN]) -> Result<(), ErError> {
+        self.key.recover(key)
+    }
+}
+
+/// Reads the key from a file once, when the provider is created. The file holds the raw
+/// 16 key bytes.
+pub struct FileKeyProvider {
+    key: HardenedKey,
+}
+
+impl FileKeyProvider {
+    pub fn open(path: &CStr) -> Result<Self, ErError> {
+        let fd = unsafe { libc::open(path.as_ptr(), libc::O_RDONLY | libc::O_CLOEXEC) };
+        if fd < 0 {
+            return Err(ErError::KeyUnavailable);
+        }
+
+        let mut key = [0u8; KEY_LEN];
+        let mut filled = 0;
+        while filled < KEY_LEN {
+            let remaining = &mut key[filled..];
+            let ret = unsafe {
+                libc::read(fd, remaining.as_mut_ptr() as *mut c_void, remaining.len())
+            };
+            if ret < 0 && unsafe { *libc::__errno_location() } == libc::EINTR {
+                continue;
+            }
+            if ret <= 0 {
+                break;
+            }
+            filled += ret as usize;
+        }
+        unsafe { libc::close(fd) };
+
+        let provider = match filled {
+            KEY_LEN => Ok(FileKeyProvider { key: HardenedKey::new(&key) }),
+            _ => Err(ErError::InvalidKey),
+        };
+        zeroize::wipe(&mut key);
+        provider
+    }
+}
+
+impl KeyProvider for FileKeyProvider {
+    fn key(&mut self, key: &mut [u8; KEY_LEN]) -> Result<(), ErError> {
+        self.key.recover(key)
+    }
+}
+
+/// Signature of the C function that supplies keys: it writes `len` key bytes to `key` and
+/// returns 0 on success.
+pub type KeyCallback = unsafe extern "C" fn(ctx: *mut c_void, key: *mut u8, len: size_t) -> c_int;
+
+/// Asks a C callback for the key every time it is needed, so the key never has to be kept by
+/// the library. The callback must not call back into ermalloc.
+pub struct CallbackKeyProvider {
+    callback: KeyCallback,
+    ctx: *mut c_void,
+}
+
+// The context is only handed back to the callback, whose thread safety is up to the caller
+unsafe impl Send for CallbackKeyProvider {}
+
+impl CallbackKeyProvider {
+    pub fn new(callback: KeyCallback, ctx: *mut c_void) -> Self {
+        CallbackKeyProvider { callback, ctx }
+    }
+}
+
+/// Stands in for a provider that could not be set up, such as a key file that could not be read
+/// when the library was loaded. Every use of the key fails with the error that was hit then.
+pub struct UnavailableKeyProvider {
+    error: ErError,
+}
+
+impl UnavailableKeyProvider {
+    pub fn new(error: ErError) -> Self {
+        UnavailableKeyProvider { error }
+    }
+}
+
+impl KeyProvider for UnavailableKeyProvider {
+    fn key(&mut self, _key: &mut [u8; KEY_LEN]) -> Result<(), ErError> {
+        Err(self.error)
+    }
+}
+
+impl KeyProvider for CallbackKeyProvider {
+    fn key(&mut self, key: &mut [u8; KEY_LEN]) -> Result<(), ErError> {
+        match unsafe { (self.callback)(self.ctx, key.as_mut_ptr(), KEY_LEN) } {
+            0 => Ok(()),
+            _ => Err(ErError::KeyUnavailable),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hardened_key_check() {
+        let original = [0x5au8; KEY_LEN];
+        let mut stored = HardenedKey::new(&original);
+        let mut key = [0u8; KEY_LEN];
+
+        // A flip in one copy is outvoted and repaired
+        stored.copies[1][3] ^= 0x10;
+        assert_eq!(stored.recover(&mut key), Ok(()));
+        assert_eq!(key, original);
+        assert_eq!(stored.copies[1], original);
+
+        // Two copies flipped at the same bit lose the vote, but the third still matches its checksum
+        stored.copies[0][7] ^= 0x01;
+        stored.copies[2][7] ^= 0x01;
+        assert_eq!(stored.recover(&mut key), Ok(()));
+        assert_eq!(key, original);
+
+        stored.copies[0][0] ^= 0x01;
+        stored.copies[1][1] ^= 0x01;
+        stored.copies[2][2] ^= 0x01;
+        stored.checksums[0] ^= 0x01;
+        stored.checksums[1] ^= 0x01;
+        stored.checksums[2] ^= 0x01;
+        assert_eq!(stored.recover(&mut key), Err(ErError::UnrecoverableKey));
+    }
+
+    #[test]
+    fn unavailable_key_check() {
+        // A key file that cannot be opened fails when its key is used, not when it is set up
+        let missing = CStr::from_bytes_with_nul(b"/nonexistent/ermalloc.key\0").unwrap();
+        let error = FileKeyProvider::open(missing).err().unwrap();
+        let mut provider = UnavailableKeyProvider::new(error);
+        let mut key = [0u8; KEY_LEN];
+        assert_eq!(provider.key(&mut key), Err(ErError::KeyUnavailable));
+    }
+}
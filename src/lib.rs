@@ -4,7 +4,19 @@
 #![allow(dead_code)]
 
 mod policies;
+mod crypto;
+mod codes;
+mod rs;
+mod vote;
+mod crc;
+mod error;
+mod keys;
+mod locked;
+mod registry;
+mod scrub;
+mod sync;
 mod weak;
+mod zeroize;
 mod ffi;
 mod alloc;
 mod panic;
@@ -0,0 +1,192 @@
+extern crate core;
+
+use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use libc;
+
+use crate::error::ErError;
+use crate::sync::SpinLock;
+
+/// Where a block's memory comes from.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LockMode {
+    /// Locked memory if the block is encrypted and locking is enabled globally.
+    Default,
+    /// Always locked memory.
+    Locked,
+    /// Always the regular heap.
+    Unlocked,
+}
+
+/// Whether encrypted blocks use locked memory by default.
+static LOCK_ENCRYPTED: AtomicBool = AtomicBool::new(true);
+
+pub fn set_lock_encrypted(enabled: bool) {
+    LOCK_ENCRYPTED.store(enabled, Ordering::Relaxed);
+}
+
+impl LockMode {
+    /// Whether a block with this mode should be locked, given whether it is encrypted.
+    pub fn resolve(self, encrypted: bool) -> bool {
+        match self {
+            LockMode::Default => encrypted && LOCK_ENCRYPTED.load(Ordering::Relaxed),
+            LockMode::Locked => true,
+            LockMode::Unlocked => false,
+        }
+    }
+}
+
+// Small allocations are carved out of locked pages in power of two slots from
+// 2^MIN_SLOT_SHIFT up to half a page. Anything larger gets its own mapping.
+const MIN_SLOT_SHIFT: usize = 6;
+const N_CLASSES: usize = 6;
+
+/// A free slot, linked through its own memory.
+struct FreeSlot {
+    next: *mut FreeSlot,
+}
+
+/// Locked memory that is handed out in slots. Pages are never given back, so freed slots
+/// stay locked until they are reused.
+struct Pool {
+    free: [*mut FreeSlot; N_CLASSES],
+}
+
+// The pool only hands out its pointers while it is locked
+unsafe impl Send for Pool {}
+
+static POOL: SpinLock<Pool> = SpinLock::new(Pool { free: [ptr::null_mut(); N_CLASSES] });
+
+fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+/// The size class of an allocation, or `None` if it needs its own mapping.
+fn class_of(size: usize) -> Option<usize> {
+    let max_slot = page_size() / 2;
+    (0..N_CLASSES)
+        .map(|class| (class, 1 << (class + MIN_SLOT_SHIFT)))
+        .find(|&(_, slot)| size <= slot && slot <= max_slot)
+        .map(|(class, _)| class)
+}
+
+fn round_to_pages(size: usize) -> usize {
+    let page = page_size();
+    (size + page - 1) / page * page
+}
+
+/// Maps `len` bytes of memory that is locked into RAM (so it is never swapped) and left out of
+/// core dumps. If the memory cannot be locked, the error is returned with `errno` set by `mlock`.
+unsafe fn map_locked(len: usize) -> Result<*mut u8, ErError> {
+    let addr = libc::mmap(
+        ptr::null_mut(),
+        len,
+        libc::PROT_READ | libc::PROT_WRITE,
+        libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+        -1,
+        0,
+    );
+    if addr == libc::MAP_FAILED {
+        return Err(ErError::LockFailed);
+    }
+    if libc::mlock(addr, len) != 0 {
+        let errno = *libc::__errno_location();
+        libc::munmap(addr, len);
+        *libc::__errno_location() = errno;
+        return Err(ErError::LockFailed);
+    }
+    // Not every kernel supports this, and the memory is still locked without it
+    libc::madvise(addr, len, libc::MADV_DONTDUMP);
+    Ok(addr as *mut u8)
+}
+
+/// Allocates `size` bytes of locked memory, aligned to at least 16 bytes.
+pub unsafe fn alloc(size: usize, zeroed: bool) -> Result<*mut u8, ErError> {
+    let class = match class_of(size) {
+        Some(class) => class,
+        // Fresh mappings are always zeroed
+        None => return map_locked(round_to_pages(size)),
+    };
+
+    let mut pool = POOL.lock();
+    if pool.free[class].is_null() {
+        let page = page_size();
+        let base = map_locked(page)?;
+        let slot_len = 1 << (class + MIN_SLOT_SHIFT);
+        for offset in (0..page).step_by(slot_len).rev() {
+            let slot = base.add(offset) as *mut FreeSlot;
+            (*slot).next = pool.free[class];
+            pool.free[class] = slot;
+        }
+    }
+
+    let slot = pool.free[class];
+    pool.free[class] = (*slot).next;
+    if zeroed {
+        ptr::write_bytes(slot as *mut u8, 0, size);
+    }
+    Ok(slot as *mut u8)
+}
+
+/// Frees memory from `alloc` that was allocated with the same `size`.
+pub unsafe fn dealloc(ptr: *mut u8, size: usize) {
+    match class_of(size) {
+        Some(class) => {
+            let slot = ptr as *mut FreeSlot;
+            let mut pool = POOL.lock();
+            (*slot).next = pool.free[class];
+            pool.free[class] = slot;
+        }
+        None => {
+            let len = round_to_pages(size);
+            libc::munlock(ptr as *const libc::c_void, len);
+            libc::munmap(ptr as *mut libc::c_void, len);
+        }
+    }
+}
+
+/// Maps `len` bytes on pages of their own, with `guard_pages` inaccessible pages before and
+/// after them, so a fault that spills over from a neighbouring mapping cannot reach them.
+/// The pages are locked like `alloc` if `lock` is set.
+pub unsafe fn map_guarded(len: usize, guard_pages: usize, lock: bool) -> Result<*mut u8, ErError> {
+    let guard_len = guard_pages * page_size();
+    let total_len = round_to_pages(len) + 2 * guard_len;
+    let addr = libc::mmap(
+        ptr::null_mut(),
+        total_len,
+        libc::PROT_NONE,
+        libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+        -1,
+        0,
+    );
+    if addr == libc::MAP_FAILED {
+        return Err(ErError::AllocFailed);
+    }
+    let data = (addr as *mut u8).add(guard_len);
+    let data_len = round_to_pages(len);
+    if libc::mprotect(data as *mut libc::c_void, data_len, libc::PROT_READ | libc::PROT_WRITE) != 0 {
+        libc::munmap(addr, total_len);
+        return Err(ErError::AllocFailed);
+    }
+    if lock {
+        if libc::mlock(data as *const libc::c_void, data_len) != 0 {
+            let errno = *libc::__errno_location();
+            libc::munmap(addr, total_len);
+            *libc::__errno_location() = errno;
+            return Err(ErError::LockFailed);
+        }
+        libc::madvise(data as *mut libc::c_void, data_len, libc::MADV_DONTDUMP);
+    }
+    Ok(data)
+}
+
+/// Unmaps memory from `map_guarded` that was mapped with the same `len` and `guard_pages`,
+/// guard pages included.
+pub unsafe fn unmap_guarded(ptr: *mut u8, len: usize, guard_pages: usize) {
+    let guard_len = guard_pages * page_size();
+    let total_len = round_to_pages(len) + 2 * guard_len;
+    // Unmapping unlocks the pages too
+    libc::munmap(ptr.sub(guard_len) as *mut libc::c_void, total_len);
+}
@@ -1,26 +1,50 @@
 extern crate alloc;
 
-use alloc::alloc::{alloc, alloc_zeroed, dealloc, realloc, Layout};
+use alloc::alloc::{alloc, alloc_zeroed, dealloc, Layout};
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
 use core::convert::TryFrom;
-use core::iter::Iterator;
+use core::iter::{Iterator, StepBy};
+use core::ops::Range;
 use core::mem::transmute;
+use core::ptr;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 
 use crate::weak::*;
 
 use reed_solomon::{Decoder, Encoder};
 
-use aes_ctr::stream_cipher::generic_array::GenericArray;
-use aes_ctr::stream_cipher::{NewStreamCipher, SyncStreamCipher};
-use aes_ctr::Aes128Ctr;
-
-pub const MAX_POLICIES: usize = 3;
-
-// AES-CTR mode with 128 bit key and 128 bit nonce
-const KEY_LEN: usize = 16;
-const NONCE_LEN: usize = 16;
-static KEY: &'static [u8] = b"very secret key.";
-// TODO: use real rng to generate the nonce (hard to do without std)
-static NONCE: &'static [u8] = b"and secret nonce";
+use aes_ctr::stream_cipher::{SyncStreamCipher, SyncStreamCipherSeek};
+use aes_gcm::aead::generic_array::GenericArray;
+use aes_gcm::aead::Aead;
+
+use crate::codes::{self, LinearCode};
+use crate::crc::{crc32, crc64};
+use crate::crypto::{self, AEAD_NONCE_LEN, KEY_ID_LEN, KEY_LEN, NONCE_LEN, TAG_LEN};
+use crate::error::ErError;
+use crate::keys::*;
+use crate::locked::{self, LockMode};
+use crate::registry::*;
+use crate::rs::{self, Codec, RsParams};
+use crate::scrub::ScrubStats;
+use crate::vote::{ElementType, TypedVote, VoteMode};
+use crate::sync::SpinLock;
+use crate::zeroize::{self, Scratch};
+
+pub const MAX_POLICIES: usize = 4;
+
+/// The most copies, including the one in the block, that `Policy::SeparatedRedundancy` keeps.
+pub const MAX_SEPARATED_COPIES: usize = 5;
+
+// The encryption trailer holds the nonce followed by the id of the key
+const CRYPT_TRAILER_LEN: usize = NONCE_LEN + KEY_ID_LEN;
+// The authenticated encryption trailer holds the nonce, the tag and the id of the key
+const AEAD_TRAILER_LEN: usize = AEAD_NONCE_LEN + TAG_LEN + KEY_ID_LEN;
+
+// A GF(2^8) Reed-Solomon codeword (data + parity) can be at most 255 symbols long,
+// so larger buffers are split into codewords of up to this many bytes
+const RS_CODEWORD_LEN: usize = 255;
 
 /// Policy comprised of some metadata about what operations are applied on the buffer.
 #[repr(u64)]
@@ -29,8 +53,49 @@ pub enum Policy {
     Nil,
     // The u32 here represents the total number of copies including the original data
     Redundancy(u32),
+    // Redundancy where every copy is followed by its own CRC-32. The first copy that matches its
+    // checksum is kept and the others are rewritten from it, so two copies are enough to
+    // recover the data. The u32 here is the total number of copies, like Redundancy
+    CheckedRedundancy(u32),
+    // Redundancy where the copies are voted on element by element, as numbers of a declared type,
+    // so the outcome is always a value that one of the copies held. The u32 here is the total
+    // number of copies, like Redundancy
+    TypedRedundancy(u32, TypedVote),
+    // Redundancy where every copy but the first lives in an allocation of its own, so a fault
+    // that takes out a page (or a DRAM row) cannot reach all of them. The u32s here are the total
+    // number of copies, like Redundancy, and the number of inaccessible guard pages around each
+    // separated copy. With no guard pages the copies come from the heap, which may put them on
+    // the same page. This must be the outermost policy
+    SeparatedRedundancy(u32, u32),
     ReedSolomon(u32),
+    // Reed Solomon with the symbols of its codewords interleaved, so a burst of adjacent bytes
+    // lands as at most one error per codeword. The u32s here are the number of parity bytes per
+    // codeword and the length of the longest burst that is spread out (the interleaving depth)
+    InterleavedReedSolomon(u32, u32),
+    // Reed Solomon that only guarantees the data. Parity that no longer matches the data is
+    // re-encoded without running the decoder, as long as the data cannot be at fault
+    DataOnlyReedSolomon(u32),
     Encrypted,
+    // Encrypted, but with a tag that detects any change to the ciphertext
+    Authenticated,
+    // A (72,64) Hamming code per 8 byte word that corrects one flipped bit and detects two
+    SecDed,
+    // SEC-DED that only corrects the data. A flip that is not in the data is taken to be in the
+    // check byte, which is re-encoded
+    DataOnlySecDed,
+    // A CRC of the data, which detects errors without correcting them.
+    // The u32 here is the width of the CRC in bits (32 or 64)
+    Checksum(u32),
+    // Unequal error protection: the bits of every word that are set in the mask are stored twice
+    // more after the data and voted on, the others are left to the outer policies. The u32 here
+    // is the width of the words in bits (8, 16, 32 or 64) and the u64 the mask
+    UnequalProtection(u32, u64),
+    // A binary linear code built from a parity-check matrix, such as a SEC-DAED code matched to
+    // the width of the memory. The u32 here is the id the code was registered under in `codes`
+    LinearCode(u32),
+    // Reed Solomon over a field and generator of our choosing, to match an outside encoder or
+    // decoder. The u32 here is the number of parity symbols per codeword
+    ReedSolomonField(u32, RsParams),
     // Custom, // TODO: Make ths a function to arbitrary data
 }
 
@@ -101,24 +166,658 @@ fn correct_bits_redundant(buffer: &mut [u8], n_copies: usize, index: usize) -> u
     errors
 }
 
+fn read_key_id(key_id: &[u8]) -> u32 {
+    let mut bytes = [0u8; KEY_ID_LEN];
+    bytes.copy_from_slice(key_id);
+    u32::from_le_bytes(bytes)
+}
+
+/// Receives the details of a correction pass that do not fit in the total error count.
+pub trait CorrectionObserver {
+    /// Called once per Reed-Solomon codeword, in buffer order, with the number of symbols
+    /// that were corrected or `None` if the codeword had too many errors to be corrected.
+    fn codeword(&mut self, _errors: Option<u32>) {}
+
+    /// Called once per typed vote with the indices of the copies that disagreed with the outcome
+    /// for at least one element.
+    fn copies_disagreed(&mut self, _copies: &[usize]) {}
+}
+
+impl CorrectionObserver for () {}
+
+/// The number of codewords needed to protect `data_len` bytes, `chunk_len` bytes to a codeword.
+fn rs_codewords(data_len: usize, chunk_len: usize) -> usize {
+    (data_len + chunk_len - 1) / chunk_len
+}
+
+/// Recovers the data length of a Reed-Solomon buffer that is `len` bytes long.
+///
+/// Every codeword but the last is exactly `chunk_len + ecc_len` bytes long and the last one holds
+/// at least one data byte, so the number of codewords can be recovered from the buffer length alone.
+fn rs_data_len(len: usize, chunk_len: usize, ecc_len: usize) -> usize {
+    let codeword_len = chunk_len + ecc_len;
+    let n_codewords = (len + codeword_len - 1) / codeword_len;
+    if len <= n_codewords * ecc_len {
+        panic!("Reed-Solomon: The number of data bits plus the amount of error correction bits is too small");
+    }
+    len - n_codewords * ecc_len
+}
+
+/// The number of codewords that interleave `data_len` bytes: at least `depth`, so a burst of
+/// `depth` bytes hits each codeword at most once, but never more than there are data bytes.
+fn rs_interleaved_codewords(data_len: usize, chunk_len: usize, depth: usize) -> usize {
+    rs_codewords(data_len, chunk_len).max(depth).min(data_len)
+}
+
+/// Same as `rs_data_len` for an interleaved buffer.
+///
+/// More than `depth` codewords are only used when the data does not fit in `depth` full ones,
+/// and then they are counted the same way. Otherwise there are `depth` codewords, or one per
+/// data byte if there are fewer data bytes.
+fn rs_interleaved_data_len(len: usize, chunk_len: usize, ecc_len: usize, depth: usize) -> usize {
+    let codeword_len = chunk_len + ecc_len;
+    let n_codewords = match (len + codeword_len - 1) / codeword_len {
+        n if n > depth => n,
+        _ if len >= depth * (ecc_len + 1) => depth,
+        _ => len / (ecc_len + 1),
+    };
+    if n_codewords == 0 || len <= n_codewords * ecc_len {
+        panic!("Reed-Solomon: The number of data bits plus the amount of error correction bits is too small");
+    }
+    len - n_codewords * ecc_len
+}
+
+/// Where the symbols of each codeword of a split Reed-Solomon buffer are kept.
+///
+/// By default a codeword is a run of data followed by its run of parity. Interleaved codewords
+/// take every `n_codewords`th byte of the data and of the parity instead, so the bytes next to
+/// any symbol belong to other codewords. The data stays in order either way.
+struct RsLayout {
+    chunk_len: usize,
+    ecc_len: usize,
+    data_len: usize,
+    n_codewords: usize,
+    interleaved: bool,
+}
+
+impl RsLayout {
+    /// The positions in the data of the data symbols of codeword `chunk`.
+    fn data_positions(&self, chunk: usize) -> StepBy<Range<usize>> {
+        match self.interleaved {
+            true => (chunk..self.data_len).step_by(self.n_codewords),
+            false => {
+                let chunk_len = self.chunk_len;
+                (chunk * chunk_len..self.data_len.min((chunk + 1) * chunk_len)).step_by(1)
+            }
+        }
+    }
+
+    /// The positions in the parity of the parity symbols of codeword `chunk`.
+    fn ecc_positions(&self, chunk: usize) -> StepBy<Range<usize>> {
+        match self.interleaved {
+            true => (chunk..self.n_codewords * self.ecc_len).step_by(self.n_codewords),
+            false => (chunk * self.ecc_len..(chunk + 1) * self.ecc_len).step_by(1),
+        }
+    }
+
+    /// Copies codeword `chunk` out of the split buffer into `codeword`.
+    /// Returns the length of the codeword.
+    fn gather(&self, data: &[u8], ecc: &[u8], chunk: usize, codeword: &mut [u8]) -> usize {
+        let symbols = self
+            .data_positions(chunk)
+            .map(|pos| data[pos])
+            .chain(self.ecc_positions(chunk).map(|pos| ecc[pos]));
+        let mut len = 0;
+        for (slot, symbol) in codeword.iter_mut().zip(symbols) {
+            *slot = symbol;
+            len += 1;
+        }
+        len
+    }
+
+    /// Copies `codeword` back to where `gather` found codeword `chunk`.
+    fn scatter(&self, codeword: &[u8], data: &mut [u8], ecc: &mut [u8], chunk: usize) {
+        let n_data = codeword.len() - self.ecc_len;
+        for (pos, &symbol) in self.data_positions(chunk).zip(codeword[..n_data].iter()) {
+            data[pos] = symbol;
+        }
+        for (pos, &symbol) in self.ecc_positions(chunk).zip(codeword[n_data..].iter()) {
+            ecc[pos] = symbol;
+        }
+    }
+}
+
+// A SEC-DED word protects this many data bytes with one check byte
+const SECDED_WORD_LEN: usize = 8;
+
+/// The Hamming positions (starting at 1) of the 64 data bits of a SEC-DED word. The 7 parity bits
+/// sit at the powers of two, so the syndrome of a single flipped bit is its position.
+const SECDED_POSITIONS: [u8; 64] = [
+    3, 5, 6, 7, 9, 10, 11, 12, 13, 14, 15, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30,
+    31, 33, 34, 35, 36, 37, 38, 39, 40, 41, 42, 43, 44, 45, 46, 47, 48, 49, 50, 51, 52, 53, 54, 55,
+    56, 57, 58, 59, 60, 61, 62, 63, 65, 66, 67, 68, 69, 70, 71,
+];
+
+/// The number of check bytes that protect `data_len` bytes.
+fn secded_words(data_len: usize) -> usize {
+    (data_len + SECDED_WORD_LEN - 1) / SECDED_WORD_LEN
+}
+
+/// Reads a word of data. The last word of a buffer may be short, and is padded with zeros.
+fn secded_word(chunk: &[u8]) -> u64 {
+    let mut word = [0u8; SECDED_WORD_LEN];
+    word[..chunk.len()].copy_from_slice(chunk);
+    u64::from_le_bytes(word)
+}
+
+/// The Hamming parity bits of a word, which are the XOR of the positions of its set bits.
+fn secded_hamming(word: u64) -> u8 {
+    SECDED_POSITIONS
+        .iter()
+        .enumerate()
+        .filter(|(bit, _)| (word >> bit) & 1 == 1)
+        .fold(0, |acc, (_, pos)| acc ^ pos)
+}
+
+/// The check byte of a word: the 7 Hamming parity bits, then a parity bit over all the others.
+fn secded_check(word: u64) -> u8 {
+    let hamming = secded_hamming(word);
+    let parity = ((word.count_ones() + hamming.count_ones()) & 1) as u8;
+    hamming | (parity << 7)
+}
+
+/// Corrects a word and its check byte in place. Returns the number of flipped bits, or `None` if
+/// more bits were flipped than can be corrected.
+fn secded_correct(chunk: &mut [u8], check: &mut u8) -> Option<u32> {
+    let word = secded_word(chunk);
+    let syndrome = secded_hamming(word) ^ (*check & 0x7f);
+    let parity_error = (word.count_ones() + check.count_ones()) & 1 == 1;
+    match (syndrome, parity_error) {
+        (0, false) => Some(0),
+        // An even number of flips leaves the parity intact but not the syndrome
+        (_, false) => None,
+        (0, true) => {
+            *check ^= 0x80;
+            Some(1)
+        }
+        (s, true) if s.is_power_of_two() => {
+            *check ^= s;
+            Some(1)
+        }
+        (s, true) => match SECDED_POSITIONS.iter().position(|&pos| pos == s) {
+            Some(bit) if bit / 8 < chunk.len() => {
+                chunk[bit / 8] ^= 1 << (bit % 8);
+                Some(1)
+            }
+            // The flip would be in the padding or past the last position, so there were more
+            _ => None,
+        },
+    }
+}
+
+/// Same as `secded_correct`, but only data bits are corrected. A single flip that is not in the
+/// data, or any odd number of flips that does not point at a data bit, is taken to be in the check
+/// byte, which is encoded again. Returns the number of bits that were changed.
+fn secded_correct_data(chunk: &mut [u8], check: &mut u8) -> Option<u32> {
+    let word = secded_word(chunk);
+    let expected = secded_check(word);
+    let syndrome = (expected ^ *check) & 0x7f;
+    let parity_error = (word.count_ones() + check.count_ones()) & 1 == 1;
+    match (syndrome, parity_error) {
+        (0, false) => Some(0),
+        (_, false) => None,
+        (s, true) => match SECDED_POSITIONS.iter().position(|&pos| pos == s) {
+            Some(bit) if bit / 8 < chunk.len() => {
+                chunk[bit / 8] ^= 1 << (bit % 8);
+                Some(1)
+            }
+            _ => {
+                let changed = (expected ^ *check).count_ones();
+                *check = expected;
+                Some(changed)
+            }
+        },
+    }
+}
+
+/// The registered linear code with the given id.
+fn linear_code(id: u32) -> &'static LinearCode {
+    codes::get(id).expect("LinearCode: No code was registered with this id")
+}
+
+/// Recovers the data length of a linear code buffer that is `len` bytes long. Every word but the
+/// last is whole, and the last one holds at least one data byte.
+fn linear_data_len(len: usize, code: &LinearCode) -> usize {
+    let word_len = code.data_bytes() + code.check_bytes();
+    let rem = len % word_len;
+    if rem != 0 && rem <= code.check_bytes() {
+        panic!("LinearCode: The buffer is not a valid length for the code");
+    }
+    let partial = match rem {
+        0 => 0,
+        rem => rem - code.check_bytes(),
+    };
+    len / word_len * code.data_bytes() + partial
+}
+
+/// Reads the check bits of a word, which are stored little-endian.
+fn read_check(bytes: &[u8]) -> u32 {
+    let mut check = [0u8; 4];
+    check[..bytes.len()].copy_from_slice(bytes);
+    u32::from_le_bytes(check)
+}
+
+fn write_check(bytes: &mut [u8], check: u32) {
+    let len = bytes.len();
+    bytes.copy_from_slice(&check.to_le_bytes()[..len]);
+}
+
+/// Computes the checksum of `data` and writes it little-endian to `out`, which is as long as the
+/// checksum.
+fn checksum(data: &[u8], out: &mut [u8]) {
+    match out.len() {
+        4 => out.copy_from_slice(&crc32(data).to_le_bytes()),
+        8 => out.copy_from_slice(&crc64(data).to_le_bytes()),
+        _ => panic!("Checksum: Only 32 and 64 bit CRCs are supported"),
+    }
+}
+
+/// Whether `data` matches the checksum stored in `stored`.
+fn checksum_matches(data: &[u8], stored: &[u8]) -> bool {
+    let mut computed = [0u8; 8];
+    checksum(data, &mut computed[..stored.len()]);
+    &computed[..stored.len()] == stored
+}
+
+// Every copy of a checked redundancy policy is followed by a CRC-32 of its data
+const CHECKED_COPY_CRC_LEN: usize = 4;
+
+/// The length of each copy (data and checksum) in a checked redundancy buffer that is `len`
+/// bytes long, and the length of the data in it.
+fn checked_copy_len(len: usize, n_copies: usize) -> (usize, usize) {
+    if len % n_copies != 0 || len / n_copies <= CHECKED_COPY_CRC_LEN {
+        panic!("Checked redundancy: Size of buffer is not a multiple of the data size plus its checksum");
+    }
+    let copy_len = len / n_copies;
+    (copy_len, copy_len - CHECKED_COPY_CRC_LEN)
+}
+
+// The significant bits of an unequal protection policy are stored this many more times, so they
+// can be voted on together with the data
+const UEP_COPIES: usize = 2;
+
+/// The width of the words in bytes and the number of significant bits per word.
+fn uep_params(width: u32, mask: u64) -> (usize, usize) {
+    match width {
+        8 | 16 | 32 | 64 => (),
+        _ => panic!("Unequal protection: The word width must be 8, 16, 32 or 64 bits"),
+    }
+    if width < 64 && mask >> width != 0 {
+        panic!("Unequal protection: The mask has bits outside of the word");
+    }
+    (width as usize / 8, mask.count_ones() as usize)
+}
+
+/// The length of each copy of the significant bits of `data_len` bytes, which are packed
+/// word after word. The last word of the data may be short, and is padded with zeros.
+fn uep_stream_len(data_len: usize, width: usize, n_bits: usize) -> usize {
+    let n_words = (data_len + width - 1) / width;
+    (n_words * n_bits + 7) / 8
+}
+
+/// Recovers the data length of an unequal protection buffer that is `len` bytes long.
+///
+/// Every 8 words take up a whole number of bytes in each copy, so only the data after the last
+/// group of 8 words has to be searched for.
+fn uep_data_len(len: usize, width: usize, n_bits: usize) -> usize {
+    let group_len = 8 * width + UEP_COPIES * n_bits;
+    let rem = len % group_len;
+    let partial = (0..8 * width)
+        .find(|&r| r + UEP_COPIES * uep_stream_len(r, width, n_bits) == rem)
+        .expect("Unequal protection: The buffer is not a valid length for the word width and mask");
+    len / group_len * 8 * width + partial
+}
+
+/// Pairs the position of every significant bit in the data with its position in the copies.
+/// Positions past the end of the data are in the padding of the last word.
+fn uep_bits(data_len: usize, width: usize, mask: u64) -> impl Iterator<Item = (usize, usize)> {
+    let width_bits = width * 8;
+    (0..(data_len + width - 1) / width)
+        .flat_map(move |word| {
+            (0..width_bits)
+                .filter(move |bit| (mask >> bit) & 1 == 1)
+                .map(move |bit| word * width_bits + bit)
+        })
+        .enumerate()
+        .map(|(stream_bit, data_bit)| (data_bit, stream_bit))
+}
+
+fn get_bit(bytes: &[u8], bit: usize) -> bool {
+    bytes.get(bit / 8).map_or(false, |byte| (byte >> (bit % 8)) & 1 == 1)
+}
+
+fn set_bit(bytes: &mut [u8], bit: usize, value: bool) {
+    if let Some(byte) = bytes.get_mut(bit / 8) {
+        match value {
+            true => *byte |= 1 << (bit % 8),
+            false => *byte &= !(1 << (bit % 8)),
+        }
+    }
+}
+
+/// The encoder and decoder of a Reed Solomon policy. `Policy::ReedSolomon` and its variants use
+/// the `reed_solomon` crate, as they always have, and only `Policy::ReedSolomonField` goes through
+/// a `Codec` for its own field and generator.
+enum RsCoder {
+    // The crate keeps its generator polynomial inline, which is large to move around
+    Crate { n_ecc: usize, enc: Box<Encoder>, dec: Box<Decoder> },
+    Field(&'static Codec),
+}
+
+impl RsCoder {
+    /// The number of parity symbols of each codeword.
+    fn n_ecc(&self) -> usize {
+        match self {
+            RsCoder::Crate { n_ecc, .. } => *n_ecc,
+            RsCoder::Field(codec) => codec.n_ecc(),
+        }
+    }
+
+    /// The number of bytes the parity of each codeword is stored in.
+    fn ecc_len(&self) -> usize {
+        match self {
+            RsCoder::Crate { n_ecc, .. } => *n_ecc,
+            RsCoder::Field(codec) => codec.ecc_len(),
+        }
+    }
+
+    /// Writes the parity of `data` to `ecc`, which is `ecc_len` bytes long.
+    fn encode(&self, data: &[u8], ecc: &mut [u8]) {
+        match self {
+            RsCoder::Crate { enc, .. } => {
+                let mut encoded = enc.encode(data);
+                ecc.copy_from_slice(encoded.ecc());
+                zeroize::wipe(&mut encoded);
+            }
+            RsCoder::Field(codec) => codec.encode(data, ecc),
+        }
+    }
+
+    /// Whether a codeword of data bytes followed by `ecc_len` parity bytes has any errors.
+    fn is_corrupted(&self, codeword: &[u8]) -> bool {
+        match self {
+            RsCoder::Crate { dec, .. } => dec.is_corrupted(codeword),
+            RsCoder::Field(codec) => codec.is_corrupted(codeword),
+        }
+    }
+
+    /// Corrects a codeword in place, given the positions of bytes that are known to be suspect.
+    /// Returns `None` if there are too many errors, in which case `codeword` is left unchanged.
+    fn correct(&self, codeword: &mut [u8], erasures: &[u8]) -> Option<()> {
+        match self {
+            RsCoder::Crate { dec, .. } => {
+                let erasures = match erasures.len() {
+                    0 => None,
+                    _ => Some(erasures),
+                };
+                let mut corrected = dec.correct(codeword, erasures).ok()?;
+                codeword.copy_from_slice(&corrected);
+                zeroize::wipe(&mut corrected);
+                Some(())
+            }
+            RsCoder::Field(codec) => codec.correct(codeword, erasures),
+        }
+    }
+}
+
+/// Repairs a codeword whose errors are all in its parity by encoding its data again, which is
+/// cheaper than decoding it. Returns the number of parity symbols that were rewritten, or `None`
+/// if the data may be at fault and the codeword has to be decoded.
+///
+/// Codewords are at least `n_ecc + 1` symbols apart, so if the parity of the data as it is differs
+/// from the stored parity in at most `n_ecc / 2` symbols, no other codeword is as close and the
+/// data is the same as the decoder would have found.
+fn rs_reencode_parity(codec: &RsCoder, codeword: &mut [u8]) -> Option<u32> {
+    let n_data = codeword.len() - codec.ecc_len();
+    let mut encoded = [0u8; RS_CODEWORD_LEN];
+    let encoded_ecc = &mut encoded[..codec.ecc_len()];
+    codec.encode(&codeword[..n_data], encoded_ecc);
+    let errors = codeword[n_data..]
+        .iter()
+        .zip(encoded_ecc.iter())
+        .filter(|(stored, expected)| stored != expected)
+        .count();
+    let repaired = match errors <= codec.n_ecc() / 2 {
+        true => {
+            codeword[n_data..].copy_from_slice(encoded_ecc);
+            Some(errors as u32)
+        }
+        false => None,
+    };
+    zeroize::wipe(&mut encoded);
+    repaired
+}
+
+/// Collects the positions within codeword `chunk` of the bytes of a Reed-Solomon buffer that are
+/// marked in `suspects`, laid out like the codeword from `RsLayout::gather`. Returns the number of
+/// positions, which may be more than `erasures` can hold; only the first ones are kept.
+fn rs_erasures(suspects: &[bool], layout: &RsLayout, chunk: usize, erasures: &mut [u8]) -> usize {
+    let positions = layout
+        .data_positions(chunk)
+        .map(|pos| suspects[pos])
+        .chain(layout.ecc_positions(chunk).map(|pos| suspects[layout.data_len + pos]))
+        .enumerate()
+        .filter(|(_, suspect)| *suspect)
+        .map(|(pos, _)| pos as u8);
+
+    let mut n = 0;
+    for pos in positions {
+        if n < erasures.len() {
+            erasures[n] = pos;
+        }
+        n += 1;
+    }
+    n
+}
+
+/// How the fields of a policy follow its discriminant.
+#[derive(Copy, Clone)]
+enum PolicyFields {
+    None,
+    U32,
+    U32U32,
+    U32U64,
+    Vote,
+    Field,
+}
+
+/// Every policy, with the layout of its fields, so a policy can be serialized from its
+/// discriminant alone.
+const POLICY_FIELDS: [(Policy, PolicyFields); 16] = [
+    (Policy::Nil, PolicyFields::None),
+    (Policy::Redundancy(0), PolicyFields::U32),
+    (Policy::CheckedRedundancy(0), PolicyFields::U32),
+    (
+        Policy::TypedRedundancy(0, TypedVote { element: ElementType::U16, mode: VoteMode::Majority, epsilon: 0.0 }),
+        PolicyFields::Vote,
+    ),
+    (Policy::SeparatedRedundancy(0, 0), PolicyFields::U32U32),
+    (Policy::ReedSolomon(0), PolicyFields::U32),
+    (Policy::InterleavedReedSolomon(0, 0), PolicyFields::U32U32),
+    (Policy::DataOnlyReedSolomon(0), PolicyFields::U32),
+    (Policy::Encrypted, PolicyFields::None),
+    (Policy::Authenticated, PolicyFields::None),
+    (Policy::SecDed, PolicyFields::None),
+    (Policy::DataOnlySecDed, PolicyFields::None),
+    (Policy::Checksum(0), PolicyFields::U32),
+    (Policy::UnequalProtection(0, 0), PolicyFields::U32U64),
+    (Policy::LinearCode(0), PolicyFields::U32),
+    (Policy::ReedSolomonField(0, rs::DEFAULT_PARAMS), PolicyFields::Field),
+];
+
+/// A policy as `repr(u64)` lays it out: a `repr(C)` struct of its discriminant and its fields.
+/// With plain numbers as the fields it can be read from any bytes, unlike a `Policy`.
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct RawPolicy<A: Copy, B: Copy> {
+    discriminant: u64,
+    a: A,
+    b: B,
+}
+
+/// A `TypedVote` read as plain numbers.
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct RawVote {
+    element: u32,
+    mode: u32,
+    epsilon: u64,
+}
+
+const _: () = assert!(core::mem::size_of::<RawVote>() == core::mem::size_of::<TypedVote>());
+
 impl Policy {
+    /// The discriminant of the policy at `policy`, read as the `u64` that `repr(u64)` puts
+    /// first.
+    unsafe fn discriminant(policy: *const Policy) -> u64 {
+        ptr::read(policy as *const u64)
+    }
+
+    /// The discriminant and the fields of the policy at `policy`, little endian and without
+    /// the padding or the unused bytes of smaller policies, so they can be checksummed.
+    ///
+    /// The bytes are read as plain numbers, since a flipped bit can leave a discriminant or a
+    /// field enum that no value has, which must not be read as a `Policy` before its checksum
+    /// is checked. A discriminant that no policy has is written on its own.
+    unsafe fn serialize(policy: *const Policy) -> [u8; POLICY_BYTES] {
+        let mut bytes = [0u8; POLICY_BYTES];
+        let discriminant = Policy::discriminant(policy);
+        bytes[..8].copy_from_slice(&discriminant.to_le_bytes());
+        let fields = POLICY_FIELDS
+            .iter()
+            .find(|(known, _)| Policy::discriminant(known) == discriminant)
+            .map(|(_, fields)| *fields);
+
+        let mut len = 8;
+        let mut put = |field: &[u8]| {
+            bytes[len..len + field.len()].copy_from_slice(field);
+            len += field.len();
+        };
+        match fields {
+            None | Some(PolicyFields::None) => (),
+            Some(PolicyFields::U32) => {
+                put(&ptr::read(policy as *const RawPolicy<u32, ()>).a.to_le_bytes());
+            }
+            Some(PolicyFields::U32U32) => {
+                let raw = ptr::read(policy as *const RawPolicy<u32, u32>);
+                put(&raw.a.to_le_bytes());
+                put(&raw.b.to_le_bytes());
+            }
+            Some(PolicyFields::U32U64) => {
+                let raw = ptr::read(policy as *const RawPolicy<u32, u64>);
+                put(&raw.a.to_le_bytes());
+                put(&raw.b.to_le_bytes());
+            }
+            Some(PolicyFields::Vote) => {
+                let raw = ptr::read(policy as *const RawPolicy<u32, RawVote>);
+                put(&raw.a.to_le_bytes());
+                put(&raw.b.element.to_le_bytes());
+                put(&raw.b.mode.to_le_bytes());
+                put(&raw.b.epsilon.to_le_bytes());
+            }
+            Some(PolicyFields::Field) => {
+                let raw = ptr::read(policy as *const RawPolicy<u32, RsParams>);
+                put(&raw.a.to_le_bytes());
+                put(&raw.b.symbol_bits.to_le_bytes());
+                put(&raw.b.polynomial.to_le_bytes());
+                put(&raw.b.first_root.to_le_bytes());
+            }
+        }
+        bytes
+    }
+
     fn is_red(&self) -> bool {
         match self {
-            Policy::Redundancy(..) => true,
+            Policy::Redundancy(..)
+            | Policy::CheckedRedundancy(..)
+            | Policy::TypedRedundancy(..)
+            | Policy::SeparatedRedundancy(..) => true,
             _ => false,
         }
     }
 
     fn is_rs(&self) -> bool {
         match self {
-            Policy::ReedSolomon(..) => true,
+            Policy::ReedSolomon(..)
+            | Policy::InterleavedReedSolomon(..)
+            | Policy::DataOnlyReedSolomon(..)
+            | Policy::ReedSolomonField(..) => true,
             _ => false,
         }
     }
 
+    /// The number of parity symbols, the field and the interleaving depth of a Reed Solomon policy.
+    fn rs_params(&self) -> (usize, RsParams, Option<usize>) {
+        let (n_ecc, params, depth) = match self {
+            Policy::ReedSolomon(n_ecc) | Policy::DataOnlyReedSolomon(n_ecc) => {
+                (*n_ecc as usize, rs::DEFAULT_PARAMS, None)
+            }
+            Policy::InterleavedReedSolomon(n_ecc, depth) => {
+                (*n_ecc as usize, rs::DEFAULT_PARAMS, Some((*depth as usize).max(1)))
+            }
+            Policy::ReedSolomonField(n_ecc, params) => (*n_ecc as usize, *params, None),
+            _ => panic!("Reed-Solomon: Not a Reed-Solomon policy"),
+        };
+        if !params.fits(n_ecc) {
+            panic!("Reed-Solomon: The number of error correction symbols must leave room for data in each codeword");
+        }
+        (n_ecc, params, depth)
+    }
+
+    /// The encoder and decoder of a Reed Solomon policy.
+    fn rs_codec(&self) -> RsCoder {
+        let (n_ecc, params, _) = self.rs_params();
+        match self {
+            Policy::ReedSolomonField(..) => RsCoder::Field(
+                rs::codec(n_ecc, &params).expect("Reed-Solomon: The field polynomial is not primitive"),
+            ),
+            _ => RsCoder::Crate {
+                n_ecc,
+                enc: Box::new(Encoder::new(n_ecc)),
+                dec: Box::new(Decoder::new(n_ecc)),
+            },
+        }
+    }
+
+    /// The layout of the split buffer of a Reed Solomon policy with `data_len` bytes of data.
+    fn rs_layout(&self, data_len: usize) -> RsLayout {
+        let (n_ecc, params, depth) = self.rs_params();
+        let chunk_len = params.chunk_len(n_ecc);
+        RsLayout {
+            chunk_len,
+            ecc_len: params.ecc_len(n_ecc),
+            data_len,
+            n_codewords: match depth {
+                Some(depth) => rs_interleaved_codewords(data_len, chunk_len, depth),
+                None => rs_codewords(data_len, chunk_len),
+            },
+            interleaved: depth.is_some(),
+        }
+    }
+
+    /// The data length of a Reed Solomon buffer that is `len` bytes long.
+    fn rs_split_len(&self, len: usize) -> usize {
+        let (n_ecc, params, depth) = self.rs_params();
+        let (chunk_len, ecc_len) = (params.chunk_len(n_ecc), params.ecc_len(n_ecc));
+        match depth {
+            Some(depth) => rs_interleaved_data_len(len, chunk_len, ecc_len, depth),
+            None => rs_data_len(len, chunk_len, ecc_len),
+        }
+    }
+
     fn is_crypt(&self) -> bool {
         match self {
-            Policy::Encrypted => true,
+            Policy::Encrypted | Policy::Authenticated => true,
             _ => false,
         }
     }
@@ -128,27 +827,56 @@ impl Policy {
     fn split_buffer_mut<'a>(&self, buffer: &'a mut [u8]) -> (&'a mut [u8], &'a mut [u8]) {
         let len = buffer.len();
         match self {
-            Policy::Redundancy(n_copies) => {
+            Policy::Redundancy(n_copies) | Policy::TypedRedundancy(n_copies, _) => {
                 if len % (*n_copies as usize) != 0 {
                     panic!("Redundancy: Size of buffer is not a multiple of the data size");
                 }
                 let data_len = len / (*n_copies as usize);
                 buffer.split_at_mut(data_len)
             }
-            Policy::ReedSolomon(n_ecc) => {
-                if len <= (*n_ecc as usize) {
-                    panic!("Reed-Solomon: The number of data bits plus the amount of error correction bits is too small");
-                }
-                let data_len = len - (*n_ecc as usize);
-                buffer.split_at_mut(data_len)
+            Policy::CheckedRedundancy(n_copies) => {
+                buffer.split_at_mut(checked_copy_len(len, *n_copies as usize).1)
             }
+            // The other copies are not in the buffer
+            Policy::SeparatedRedundancy(..) => buffer.split_at_mut(len),
+            Policy::ReedSolomon(..)
+            | Policy::InterleavedReedSolomon(..)
+            | Policy::DataOnlyReedSolomon(..)
+            | Policy::ReedSolomonField(..) => buffer.split_at_mut(self.rs_split_len(len)),
             Policy::Encrypted => {
-                if len <= NONCE_LEN {
+                if len <= CRYPT_TRAILER_LEN {
                     panic!("Encryption: The number of ciphertext bits plus the number of nonce bits is too small");
                 }
-                let data_len = len - NONCE_LEN;
+                let data_len = len - CRYPT_TRAILER_LEN;
+                buffer.split_at_mut(data_len)
+            }
+            Policy::Authenticated => {
+                if len <= AEAD_TRAILER_LEN {
+                    panic!("Authenticated encryption: The number of ciphertext bits plus the number of nonce and tag bits is too small");
+                }
+                let data_len = len - AEAD_TRAILER_LEN;
+                buffer.split_at_mut(data_len)
+            }
+            Policy::Checksum(width) => {
+                let checksum_len = (*width / 8) as usize;
+                if len <= checksum_len {
+                    panic!("Checksum: The number of data bits plus the checksum bits is too small");
+                }
+                buffer.split_at_mut(len - checksum_len)
+            }
+            Policy::SecDed | Policy::DataOnlySecDed => {
+                if len < 2 {
+                    panic!("SEC-DED: The buffer is too small to hold data and a check byte");
+                }
+                // Every 8 data bytes (or fewer, for the last word) come with one check byte
+                let data_len = len - (len + SECDED_WORD_LEN) / (SECDED_WORD_LEN + 1);
                 buffer.split_at_mut(data_len)
             }
+            Policy::UnequalProtection(width, mask) => {
+                let (width, n_bits) = uep_params(*width, *mask);
+                buffer.split_at_mut(uep_data_len(len, width, n_bits))
+            }
+            Policy::LinearCode(id) => buffer.split_at_mut(linear_data_len(len, linear_code(*id))),
             _ => buffer.split_at_mut(buffer.len() - 1),
         }
     }
@@ -157,37 +885,65 @@ impl Policy {
     fn split_buffer<'a>(&self, buffer: &'a [u8]) -> (&'a [u8], &'a [u8]) {
         let len = buffer.len();
         match self {
-            Policy::Redundancy(n_copies) => {
+            Policy::Redundancy(n_copies) | Policy::TypedRedundancy(n_copies, _) => {
                 if len % (*n_copies as usize) != 0 {
                     panic!("Redundancy: Size of buffer is not a multiple of the data size");
                 }
                 let data_len = len / (*n_copies as usize);
                 buffer.split_at(data_len)
             }
-            Policy::ReedSolomon(n_ecc) => {
-                if len <= (*n_ecc as usize) {
-                    panic!("Reed-Solomon: The number of data bits plus the amount of error correction bits is too small");
-                }
-                let data_len = len - (*n_ecc as usize);
-                buffer.split_at(data_len)
+            Policy::CheckedRedundancy(n_copies) => {
+                buffer.split_at(checked_copy_len(len, *n_copies as usize).1)
             }
+            Policy::SeparatedRedundancy(..) => buffer.split_at(len),
+            Policy::ReedSolomon(..)
+            | Policy::InterleavedReedSolomon(..)
+            | Policy::DataOnlyReedSolomon(..)
+            | Policy::ReedSolomonField(..) => buffer.split_at(self.rs_split_len(len)),
             Policy::Encrypted => {
-                if len <= NONCE_LEN {
+                if len <= CRYPT_TRAILER_LEN {
                     panic!("Encryption: The number of ciphertext bits plus the number of nonce bits is too small");
                 }
-                let data_len = len - NONCE_LEN;
+                let data_len = len - CRYPT_TRAILER_LEN;
+                buffer.split_at(data_len)
+            }
+            Policy::Authenticated => {
+                if len <= AEAD_TRAILER_LEN {
+                    panic!("Authenticated encryption: The number of ciphertext bits plus the number of nonce and tag bits is too small");
+                }
+                let data_len = len - AEAD_TRAILER_LEN;
+                buffer.split_at(data_len)
+            }
+            Policy::Checksum(width) => {
+                let checksum_len = (*width / 8) as usize;
+                if len <= checksum_len {
+                    panic!("Checksum: The number of data bits plus the checksum bits is too small");
+                }
+                buffer.split_at(len - checksum_len)
+            }
+            Policy::SecDed | Policy::DataOnlySecDed => {
+                if len < 2 {
+                    panic!("SEC-DED: The buffer is too small to hold data and a check byte");
+                }
+                // Every 8 data bytes (or fewer, for the last word) come with one check byte
+                let data_len = len - (len + SECDED_WORD_LEN) / (SECDED_WORD_LEN + 1);
                 buffer.split_at(data_len)
             }
+            Policy::UnequalProtection(width, mask) => {
+                let (width, n_bits) = uep_params(*width, *mask);
+                buffer.split_at(uep_data_len(len, width, n_bits))
+            }
+            Policy::LinearCode(id) => buffer.split_at(linear_data_len(len, linear_code(*id))),
             _ => buffer.split_at(buffer.len() - 1),
         }
     }
 
     /// Determines if the slice is corrupted if the current policy was used to correct the data.
     fn is_corrupted(&self, buffer: &[u8]) -> bool {
-        let (data, _ecc) = self.split_buffer(buffer);
+        let (data, ecc) = self.split_buffer(buffer);
 
         match self {
-            Policy::Redundancy(n_copies) => {
+            Policy::Redundancy(n_copies) | Policy::TypedRedundancy(n_copies, _) => {
                 let data_len = data.len();
                 for byte in 0..data_len {
                     let val = buffer[byte];
@@ -201,9 +957,47 @@ impl Policy {
                 }
                 false
             }
-            Policy::ReedSolomon(n_ecc) => {
-                let dec = Decoder::new(*n_ecc as usize);
-                dec.is_corrupted(buffer)
+            Policy::CheckedRedundancy(n_copies) => {
+                let (copy_len, data_len) = checked_copy_len(buffer.len(), *n_copies as usize);
+                let first = &buffer[..copy_len];
+                buffer.chunks_exact(copy_len).any(|copy| {
+                    copy != first || !checksum_matches(&copy[..data_len], &copy[data_len..])
+                })
+            }
+            Policy::ReedSolomon(..)
+            | Policy::InterleavedReedSolomon(..)
+            | Policy::DataOnlyReedSolomon(..)
+            | Policy::ReedSolomonField(..) => {
+                let layout = self.rs_layout(data.len());
+                let codec = self.rs_codec();
+                let mut codeword = [0u8; RS_CODEWORD_LEN];
+                let corrupted = (0..layout.n_codewords).any(|chunk| {
+                    let len = layout.gather(data, ecc, chunk, &mut codeword);
+                    codec.is_corrupted(&codeword[..len])
+                });
+                zeroize::wipe(&mut codeword);
+                corrupted
+            }
+            Policy::Checksum(_) => !checksum_matches(data, ecc),
+            Policy::SecDed | Policy::DataOnlySecDed => data
+                .chunks(SECDED_WORD_LEN)
+                .zip(ecc.iter())
+                .any(|(chunk, &check)| secded_check(secded_word(chunk)) != check),
+            Policy::LinearCode(id) => {
+                let code = linear_code(*id);
+                data.chunks(code.data_bytes())
+                    .zip(ecc.chunks_exact(code.check_bytes()))
+                    .any(|(chunk, check)| {
+                        code.encode(secded_word(chunk)) != read_check(check) & code.check_mask()
+                    })
+            }
+            Policy::UnequalProtection(width, mask) => {
+                let (width, _) = uep_params(*width, *mask);
+                let (first, second) = ecc.split_at(ecc.len() / UEP_COPIES);
+                uep_bits(data.len(), width, *mask).any(|(data_bit, stream_bit)| {
+                    let value = get_bit(data, data_bit);
+                    get_bit(first, stream_bit) != value || get_bit(second, stream_bit) != value
+                })
             }
             _ => false,
         }
@@ -218,31 +1012,258 @@ impl Policy {
     ///
     /// Reed Solomon will first attempt to correct the buffer, if there are too many errors for it to handle,
     /// then redundancy should take care of it. Without redundancy, an incorrect buffer can be returned to user.
+    /// Codes that detect more errors than they correct, such as SEC-DED, return `ErError::Uncorrectable`
+    /// instead.
     /// 
+    /// Each Reed Solomon codeword (or SEC-DED word) is corrected independently and reported to `observer`.
+    /// The ones that can be corrected are, even if others cannot.
+    ///
     /// # Arguments
     /// * `buffer` - The buffer that the policy applies to
-    fn correct_buffer(&self, buffer: &mut [u8]) -> u32 {
+    /// * `suspects` - Marks the bytes of `buffer` that are known to be suspect, which Reed Solomon
+    /// treats as erasures
+    /// * `observer` - Receives per-codeword results
+    fn correct_buffer(
+        &self,
+        buffer: &mut [u8],
+        suspects: Option<&[bool]>,
+        observer: &mut dyn CorrectionObserver,
+    ) -> Result<u32, ErError> {
         match self {
             Policy::Redundancy(n_copies) => {
                 let (data, _) = self.split_buffer(buffer);
                 let n_copies = *n_copies as usize;
-                (0..data.len())
+                Ok((0..data.len())
                     .map(|index| correct_bits_redundant(buffer, n_copies, index))
-                    .sum()
-            }
-            Policy::ReedSolomon(correction_bits) => {
-                let dec = Decoder::new(*correction_bits as usize);
-                // If reed solomon is incapable of correcting, then let redundancy handle it
-                let (corrected, n_errors) = match dec.correct_err_count(buffer, None) {
-                    Ok(res) => res,
-                    Err(_e) => return 0,
+                    .sum())
+            }
+            Policy::TypedRedundancy(n_copies, vote) => {
+                let data_len = self.split_buffer(buffer).0.len();
+                let n_copies = *n_copies as usize;
+                let size = vote.element.size();
+                let n_elements = data_len / size;
+                let mut values = Vec::with_capacity(n_copies);
+                let mut disagreed = vec![false; n_copies];
+                let mut n_errors = 0;
+                let mut uncorrectable = false;
+                for element in 0..n_elements {
+                    let offset = element * size;
+                    values.clear();
+                    values.extend(
+                        (0..n_copies).map(|copy| vote.element.read(&buffer[copy * data_len + offset..])),
+                    );
+                    let winner = match vote.winner(&values) {
+                        Some(winner) => winner,
+                        None => {
+                            uncorrectable = true;
+                            continue;
+                        }
+                    };
+                    for copy in (0..n_copies).filter(|&copy| values[copy] != values[winner]) {
+                        disagreed[copy] |= !vote.agrees(values[winner], values[copy]);
+                        buffer.copy_within(
+                            winner * data_len + offset..winner * data_len + offset + size,
+                            copy * data_len + offset,
+                        );
+                        n_errors += 1;
+                    }
+                }
+                // Bytes after the last whole element are voted on bit by bit
+                n_errors += (n_elements * size..data_len)
+                    .map(|index| correct_bits_redundant(buffer, n_copies, index))
+                    .sum::<u32>();
+
+                let copies: Vec<usize> = (0..n_copies).filter(|&copy| disagreed[copy]).collect();
+                observer.copies_disagreed(&copies);
+                match uncorrectable {
+                    true => Err(ErError::Uncorrectable),
+                    false => Ok(n_errors),
+                }
+            }
+            Policy::CheckedRedundancy(n_copies) => {
+                let n_copies = *n_copies as usize;
+                let (copy_len, data_len) = checked_copy_len(buffer.len(), n_copies);
+                let verified = buffer.chunks_exact(copy_len).position(|copy| {
+                    let (data, stored) = copy.split_at(data_len);
+                    checksum_matches(data, stored)
+                });
+                match verified {
+                    Some(good) => {
+                        let mut n_errors = 0;
+                        for copy in (0..n_copies).filter(|&copy| copy != good) {
+                            for byte in 0..copy_len {
+                                let (from, to) = (good * copy_len + byte, copy * copy_len + byte);
+                                n_errors += (buffer[from] ^ buffer[to]).count_ones();
+                                buffer[to] = buffer[from];
+                            }
+                        }
+                        Ok(n_errors)
+                    }
+                    // No copy can be trusted on its own, so vote on them (checksums included) and
+                    // check the result
+                    None => {
+                        let n_errors = (0..copy_len)
+                            .map(|index| correct_bits_redundant(buffer, n_copies, index))
+                            .sum();
+                        let (data, stored) = buffer[..copy_len].split_at(data_len);
+                        match checksum_matches(data, stored) {
+                            true => Ok(n_errors),
+                            false => Err(ErError::Uncorrectable),
+                        }
+                    }
+                }
+            }
+            Policy::ReedSolomon(..)
+            | Policy::InterleavedReedSolomon(..)
+            | Policy::DataOnlyReedSolomon(..)
+            | Policy::ReedSolomonField(..) => {
+                let (data, ecc) = self.split_buffer_mut(buffer);
+                let layout = self.rs_layout(data.len());
+                let codec = self.rs_codec();
+                let n_ecc = codec.n_ecc();
+                let data_only = match self {
+                    Policy::DataOnlyReedSolomon(_) => true,
+                    _ => false,
+                };
+                let mut codeword = [0u8; RS_CODEWORD_LEN];
+                let mut corrected = [0u8; RS_CODEWORD_LEN];
+                let mut erasures = [0u8; RS_CODEWORD_LEN];
+                let mut n_errors = 0;
+                for chunk in 0..layout.n_codewords {
+                    let len = layout.gather(data, ecc, chunk, &mut codeword);
+                    if data_only {
+                        if let Some(errors) = rs_reencode_parity(&codec, &mut codeword[..len]) {
+                            layout.scatter(&codeword[..len], data, ecc, chunk);
+                            observer.codeword(Some(errors));
+                            n_errors += errors;
+                            continue;
+                        }
+                    }
+                    let n_erasures = match suspects {
+                        Some(suspects) => rs_erasures(suspects, &layout, chunk, &mut erasures),
+                        None => 0,
+                    };
+
+                    // Each erasure costs half as much of the parity as an error at an unknown
+                    // position. If there are too many, or the hints were wrong, decode without them
+                    corrected[..len].copy_from_slice(&codeword[..len]);
+                    let decoded = match n_erasures {
+                        n if n > 0 && n <= n_ecc => codec
+                            .correct(&mut corrected[..len], &erasures[..n])
+                            .or_else(|| codec.correct(&mut corrected[..len], &[])),
+                        _ => codec.correct(&mut corrected[..len], &[]),
+                    };
+                    // If reed solomon is incapable of correcting, then let redundancy handle it
+                    if decoded.is_none() {
+                        observer.codeword(None);
+                        continue;
+                    }
+                    // Erasures may have been right all along, so count the symbols that actually
+                    // changed
+                    let errors = codeword[..len]
+                        .iter()
+                        .zip(corrected[..len].iter())
+                        .filter(|(before, after)| before != after)
+                        .count();
+                    layout.scatter(&corrected[..len], data, ecc, chunk);
+                    observer.codeword(Some(errors as u32));
+                    n_errors += errors as u32;
+                }
+                zeroize::wipe(&mut codeword);
+                zeroize::wipe(&mut corrected);
+                zeroize::wipe(&mut erasures);
+                Ok(n_errors)
+            }
+            Policy::Checksum(_) => {
+                let (data, stored) = self.split_buffer(buffer);
+                match checksum_matches(data, stored) {
+                    true => Ok(0),
+                    false => Err(ErError::Uncorrectable),
+                }
+            }
+            Policy::SecDed | Policy::DataOnlySecDed => {
+                let data_only = match self {
+                    Policy::DataOnlySecDed => true,
+                    _ => false,
                 };
                 let (data, ecc) = self.split_buffer_mut(buffer);
-                data.clone_from_slice(corrected.data());
-                ecc.clone_from_slice(corrected.ecc());
-                n_errors as u32
+                let mut n_errors = 0;
+                let mut uncorrectable = false;
+                for (chunk, check) in data.chunks_mut(SECDED_WORD_LEN).zip(ecc.iter_mut()) {
+                    let errors = match data_only {
+                        true => secded_correct_data(chunk, check),
+                        false => secded_correct(chunk, check),
+                    };
+                    observer.codeword(errors);
+                    match errors {
+                        Some(n) => n_errors += n,
+                        None => uncorrectable = true,
+                    }
+                }
+                match uncorrectable {
+                    true => Err(ErError::Uncorrectable),
+                    false => Ok(n_errors),
+                }
+            }
+            Policy::LinearCode(id) => {
+                let code = linear_code(*id);
+                let (data, ecc) = self.split_buffer_mut(buffer);
+                let mut n_errors = 0;
+                let mut uncorrectable = false;
+                for (chunk, check_bytes) in data
+                    .chunks_mut(code.data_bytes())
+                    .zip(ecc.chunks_exact_mut(code.check_bytes()))
+                {
+                    let mut word = secded_word(chunk);
+                    let mut check = read_check(check_bytes) & code.check_mask();
+                    let errors = code.correct(&mut word, &mut check);
+                    // A correction in the padding of a short word means there were more errors
+                    let padding = match chunk.len() {
+                        SECDED_WORD_LEN => 0,
+                        len => word >> (8 * len),
+                    };
+                    let errors = match padding {
+                        0 => errors,
+                        _ => None,
+                    };
+                    observer.codeword(errors);
+                    match errors {
+                        Some(n) => {
+                            n_errors += n;
+                            chunk.copy_from_slice(&word.to_le_bytes()[..chunk.len()]);
+                            write_check(check_bytes, check);
+                        }
+                        None => uncorrectable = true,
+                    }
+                }
+                match uncorrectable {
+                    true => Err(ErError::Uncorrectable),
+                    false => Ok(n_errors),
+                }
+            }
+            Policy::UnequalProtection(width, mask) => {
+                let (width, _) = uep_params(*width, *mask);
+                let (data, ecc) = self.split_buffer_mut(buffer);
+                let stream_len = ecc.len() / UEP_COPIES;
+                let (first, second) = ecc.split_at_mut(stream_len);
+                let mut n_errors = 0;
+                for (data_bit, stream_bit) in uep_bits(data.len(), width, *mask) {
+                    let votes = [
+                        get_bit(data, data_bit),
+                        get_bit(first, stream_bit),
+                        get_bit(second, stream_bit),
+                    ];
+                    let ones = votes.iter().filter(|&&vote| vote).count();
+                    let value = ones * 2 > votes.len();
+                    n_errors += votes.iter().filter(|&&vote| vote != value).count() as u32;
+                    // The padding of the last word is never written
+                    set_bit(data, data_bit, value);
+                    set_bit(first, stream_bit, value);
+                    set_bit(second, stream_bit, value);
+                }
+                Ok(n_errors)
             }
-            _ => 0,
+            _ => Ok(0),
         }
     }
 
@@ -255,9 +1276,10 @@ impl Policy {
     /// 
     /// # Arguments
     /// * `buffer` - The buffer that the policy applies to
-    fn apply_policy(&self, buffer: &mut [u8]) {
+    /// * `block_id` - The id of the block that owns the buffer, which encryption keys are derived for
+    fn apply_policy(&self, buffer: &mut [u8], block_id: u64) -> Result<(), ErError> {
         match self {
-            Policy::Redundancy(n_copies) => {
+            Policy::Redundancy(n_copies) | Policy::TypedRedundancy(n_copies, _) => {
                 if buffer.len() % (*n_copies as usize) != 0 {
                     panic!("Redundancy: Size of buffer is not a multiple of the data size");
                 }
@@ -267,24 +1289,173 @@ impl Policy {
                     slice.copy_from_slice(data)
                 }
             }
-            Policy::ReedSolomon(correction_bits) => {
-                let enc = Encoder::new(*correction_bits as usize);
-                let (data, err) = self.split_buffer_mut(buffer);
-                let encoded = enc.encode(data);
-                err.copy_from_slice(encoded.ecc());
+            Policy::CheckedRedundancy(n_copies) => {
+                let (copy_len, data_len) = checked_copy_len(buffer.len(), *n_copies as usize);
+                let (first, rest) = buffer.split_at_mut(copy_len);
+                let (data, stored) = first.split_at_mut(data_len);
+                checksum(data, stored);
+                for copy in rest.chunks_exact_mut(copy_len) {
+                    copy.copy_from_slice(first);
+                }
             }
-            Policy::Encrypted => {
-                let key = GenericArray::from_slice(KEY);
-                // let random_bytes = rand::thread_rng().gen::<[u8; NONCE_LEN]>();
-                // let nonce = GenericArray::from_slice(&random_bytes);
-                let nonce = GenericArray::from_slice(NONCE);
-                let mut cipher = Aes128Ctr::new(&key, &nonce);
-                let (mut data, err) = self.split_buffer_mut(buffer);
-                cipher.apply_keystream(&mut data);
-                err.copy_from_slice(NONCE);
+            Policy::ReedSolomon(..)
+            | Policy::InterleavedReedSolomon(..)
+            | Policy::DataOnlyReedSolomon(..)
+            | Policy::ReedSolomonField(..) => {
+                let (data, ecc) = self.split_buffer_mut(buffer);
+                let layout = self.rs_layout(data.len());
+                let codec = self.rs_codec();
+                let mut codeword = [0u8; RS_CODEWORD_LEN];
+                for chunk in 0..layout.n_codewords {
+                    let len = layout.gather(data, ecc, chunk, &mut codeword);
+                    let (chunk_data, chunk_ecc) = codeword[..len].split_at_mut(len - layout.ecc_len);
+                    codec.encode(chunk_data, chunk_ecc);
+                    layout.scatter(&codeword[..len], data, ecc, chunk);
+                }
+                zeroize::wipe(&mut codeword);
+            }
+            Policy::Checksum(_) => {
+                let (data, stored) = self.split_buffer_mut(buffer);
+                checksum(data, stored);
+            }
+            Policy::SecDed | Policy::DataOnlySecDed => {
+                let (data, ecc) = self.split_buffer_mut(buffer);
+                for (chunk, check) in data.chunks(SECDED_WORD_LEN).zip(ecc.iter_mut()) {
+                    *check = secded_check(secded_word(chunk));
+                }
+            }
+            Policy::LinearCode(id) => {
+                let code = linear_code(*id);
+                let (data, ecc) = self.split_buffer_mut(buffer);
+                for (chunk, check) in data
+                    .chunks(code.data_bytes())
+                    .zip(ecc.chunks_exact_mut(code.check_bytes()))
+                {
+                    write_check(check, code.encode(secded_word(chunk)));
+                }
+            }
+            Policy::UnequalProtection(width, mask) => {
+                let (width, _) = uep_params(*width, *mask);
+                let (data, ecc) = self.split_buffer_mut(buffer);
+                let stream_len = ecc.len() / UEP_COPIES;
+                for (data_bit, stream_bit) in uep_bits(data.len(), width, *mask) {
+                    let value = get_bit(data, data_bit);
+                    for copy in ecc.chunks_exact_mut(stream_len) {
+                        set_bit(copy, stream_bit, value);
+                    }
+                }
+            }
+            Policy::Encrypted | Policy::Authenticated => {
+                // Every write gets a fresh nonce under the current key, which are stored in
+                // the trailer for decryption
+                let (_, err) = self.split_buffer_mut(buffer);
+                let (nonce, _, key_id) = self.split_trailer_mut(err);
+                crypto::fill_random(nonce);
+                key_id.copy_from_slice(&crypto::current_key_id().to_le_bytes());
+                self.encrypt(buffer, block_id)?;
             }
             _ => (),
         }
+        Ok(())
+    }
+
+    /// Splits the trailer of an encryption policy into (`nonce`, `tag`, `key_id`).
+    /// The tag is empty unless the encryption is authenticated.
+    fn split_trailer_mut<'a>(&self, trailer: &'a mut [u8]) -> (&'a mut [u8], &'a mut [u8], &'a mut [u8]) {
+        let nonce_len = match self {
+            Policy::Authenticated => AEAD_NONCE_LEN,
+            _ => NONCE_LEN,
+        };
+        let (nonce, rest) = trailer.split_at_mut(nonce_len);
+        let tag_len = rest.len() - KEY_ID_LEN;
+        let (tag, key_id) = rest.split_at_mut(tag_len);
+        (nonce, tag, key_id)
+    }
+
+    /// Encrypts the data under the nonce and key that are stored in the trailer, with the key
+    /// derived for the block. For authenticated encryption the tag is written next to the nonce.
+    fn encrypt(&self, buffer: &mut [u8], block_id: u64) -> Result<(), ErError> {
+        let (mut data, err) = self.split_buffer_mut(buffer);
+        let (nonce, tag, key_id) = self.split_trailer_mut(err);
+        match self {
+            Policy::Encrypted => {
+                let mut cipher = crypto::cipher(read_key_id(key_id), block_id, nonce)?;
+                cipher.apply_keystream(&mut data);
+            }
+            Policy::Authenticated => {
+                // The key id is authenticated too, so a flip in it cannot go unnoticed
+                let computed = crypto::aead(read_key_id(key_id), block_id)?
+                    .encrypt_in_place_detached(GenericArray::from_slice(nonce), key_id, data)
+                    .expect("Authenticated encryption: Buffer is too large to encrypt");
+                tag.copy_from_slice(&computed);
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// The id of the key the data is encrypted under.
+    fn key_id(&self, buffer: &[u8]) -> u32 {
+        let (_, err) = self.split_buffer(buffer);
+        read_key_id(&err[err.len() - KEY_ID_LEN..])
+    }
+
+    /// Decrypts the data with the nonce and key stored in the trailer.
+    ///
+    /// For authenticated encryption the data is only decrypted if the tag matches,
+    /// otherwise it is left as ciphertext and `ErError::TagMismatch` is returned.
+    fn decrypt(&self, buffer: &mut [u8], block_id: u64) -> Result<(), ErError> {
+        let (mut data, err) = self.split_buffer_mut(buffer);
+        let (nonce, tag, key_id) = self.split_trailer_mut(err);
+        match self {
+            Policy::Encrypted => {
+                let mut cipher = crypto::cipher(read_key_id(key_id), block_id, nonce)?;
+                cipher.apply_keystream(&mut data);
+                Ok(())
+            }
+            Policy::Authenticated => crypto::aead(read_key_id(key_id), block_id)?
+                .decrypt_in_place_detached(
+                    GenericArray::from_slice(nonce),
+                    key_id,
+                    data,
+                    GenericArray::from_slice(tag),
+                )
+                .map_err(|_| ErError::TagMismatch),
+            _ => Ok(()),
+        }
+    }
+
+    /// Decrypts `dest.len()` bytes of the data, starting `offset` bytes in, into `dest`. The buffer
+    /// itself is left as ciphertext.
+    ///
+    /// The CTR keystream is seeked to `offset`, so only the requested bytes are decrypted.
+    /// Authenticated data can only be checked against its tag as a whole, so it is decrypted in
+    /// a scratch copy instead, which is wiped afterwards.
+    fn decrypt_range(
+        &self,
+        buffer: &[u8],
+        block_id: u64,
+        offset: usize,
+        dest: &mut [u8],
+    ) -> Result<(), ErError> {
+        match self {
+            Policy::Encrypted => {
+                let (data, err) = self.split_buffer(buffer);
+                let nonce = &err[..NONCE_LEN];
+                let key_id = read_key_id(&err[NONCE_LEN..]);
+                let mut cipher = crypto::cipher(key_id, block_id, nonce)?;
+                dest.copy_from_slice(&data[offset..offset + dest.len()]);
+                cipher.seek(offset as u64);
+                cipher.apply_keystream(dest);
+            }
+            Policy::Authenticated => {
+                let mut scratch = Scratch::from(buffer);
+                self.decrypt(&mut scratch, block_id)?;
+                dest.copy_from_slice(&scratch[offset..offset + dest.len()]);
+            }
+            _ => dest.copy_from_slice(&self.get_data(buffer)[offset..offset + dest.len()]),
+        }
+        Ok(())
     }
 
     /// A convenience method to just extract the data bits from the buffer
@@ -323,6 +1494,64 @@ impl Policy {
 /// ```
 #[repr(C)]
 pub struct AllocBlock {
+    // The metadata that stays the same for the lifetime of the block, and its CRC-32
+    header: BlockHeader,
+    header_crc: u32,
+
+    // A second copy of the header and its CRC-32, which a corrupted header is restored from
+    backup: BlockHeader,
+    backup_crc: u32,
+
+    // A WeakMut holds a references
+    // We can figure out how we want to manage this thing later
+    // It changes too often to be checksummed, so it is stored as all ones or all zeros and a
+    // few flipped bits are outvoted by the rest
+    weak_exists: u32,
+
+    // Held while the block is corrected or rewritten, see `lock_usr_ptr`. It is stored like
+    // weak_exists, so a few flipped bits cannot lock a block for good
+    in_use: AtomicU32,
+
+    // Links to the other live blocks
+    links: Links<AllocBlock>,
+
+    // BLOCK_LIVE or BLOCK_FREED. It is the last field, right before the data, so a pointer
+    // can be checked without reading anything else from before it
+    state: u64,
+}
+
+/// The state of a live block, which doubles as its magic number ("ermalloc").
+const BLOCK_LIVE: u64 = 0x6572_6d61_6c6c_6f63;
+
+/// The state of a freed block. It is the complement of `BLOCK_LIVE`, so flipped bits cannot
+/// turn one into the other.
+const BLOCK_FREED: u64 = !BLOCK_LIVE;
+
+/// How many flipped bits in the state are tolerated (and repaired, for a live block).
+const STATE_TOLERANCE: u32 = 8;
+
+/// What freed blocks are filled with, so data read through a dangling pointer stands out.
+const FREED_POISON: u8 = 0xdf;
+
+// A policy is serialized as its discriminant followed by at most 20 bytes of fields
+const POLICY_BYTES: usize = 8 + 20;
+
+// The serialized policies, the sizes and id, the lock flag and the copy pointers
+const HEADER_BYTES: usize = POLICY_BYTES * MAX_POLICIES
+    + 2 * core::mem::size_of::<usize>()
+    + 8
+    + 1
+    + (MAX_SEPARATED_COPIES - 1) * core::mem::size_of::<usize>();
+
+/// The part of a block's metadata that every operation on it trusts. A flipped bit in the
+/// buffer size or in a policy would send error correction out of bounds, so it is checked
+/// against its checksum (and repaired from the backup) before it is used.
+///
+/// The checksum covers the fields of the header one by one rather than its bytes in memory,
+/// which include padding and the unused bytes of smaller policies.
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct BlockHeader {
     /// Policies to be applied to the data.
     /// Policies are applied in reverse order from MAX_POLICIES - 1 to 0.
     policies: [Policy; MAX_POLICIES],
@@ -333,22 +1562,115 @@ pub struct AllocBlock {
     // The amount of the data allocated (as specified by the user)
     length: usize,
 
-    // A WeakMut holds a references
-    // We can figure out how we want to manage this thing later
-    weak_exists: bool,
+    // Unique for the lifetime of the process, so every block encrypts under its own derived key
+    id: u64,
+
+    // Whether the block lives in locked memory rather than on the heap
+    locked: bool,
+
+    // The copies of the buffer that live in allocations of their own, for SeparatedRedundancy.
+    // The first copy is the buffer after the block
+    copies: [*mut u8; MAX_SEPARATED_COPIES - 1],
+}
+
+impl BlockHeader {
+    /// The CRC-32 of the fields of the header at `header`. They are read through the pointer,
+    /// since the header is not known to hold valid policies until this matches its checksum.
+    unsafe fn checksum(header: *const BlockHeader) -> u32 {
+        let mut bytes = [0u8; HEADER_BYTES];
+        let mut len = 0;
+        let mut put = |field: &[u8]| {
+            bytes[len..len + field.len()].copy_from_slice(field);
+            len += field.len();
+        };
+        let policies = ptr::addr_of!((*header).policies) as *const Policy;
+        for index in 0..MAX_POLICIES {
+            put(&Policy::serialize(policies.add(index)));
+        }
+        put(&ptr::read(ptr::addr_of!((*header).buffer_size)).to_le_bytes());
+        put(&ptr::read(ptr::addr_of!((*header).length)).to_le_bytes());
+        put(&ptr::read(ptr::addr_of!((*header).id)).to_le_bytes());
+        // Read as a byte, since a flipped bit can make it neither true nor false
+        put(&[ptr::read(ptr::addr_of!((*header).locked) as *const u8)]);
+        let copies = ptr::addr_of!((*header).copies) as *const usize;
+        for copy in 0..MAX_SEPARATED_COPIES - 1 {
+            put(&ptr::read(copies.add(copy)).to_le_bytes());
+        }
+        crc32(&bytes[..len])
+    }
+}
+
+/// Allocates a copy of a `len` byte buffer for `Policy::SeparatedRedundancy`: from the heap
+/// (or locked memory, if `lock` is set) without guard pages, or on pages of its own with them.
+unsafe fn alloc_copy(len: usize, guard_pages: usize, lock: bool) -> Result<*mut u8, ErError> {
+    match (guard_pages, lock) {
+        (0, true) => locked::alloc(len, false),
+        (0, false) => match alloc(Layout::from_size_align(len, 16).unwrap()) {
+            ptr if ptr.is_null() => Err(ErError::AllocFailed),
+            ptr => Ok(ptr),
+        },
+        (_, _) => locked::map_guarded(len, guard_pages, lock),
+    }
+}
+
+/// Frees a copy from `alloc_copy` that was allocated with the same arguments.
+unsafe fn dealloc_copy(ptr: *mut u8, len: usize, guard_pages: usize, lock: bool) {
+    match (guard_pages, lock) {
+        (0, true) => locked::dealloc(ptr, len),
+        (0, false) => dealloc(ptr, Layout::from_size_align(len, 16).unwrap()),
+        (_, _) => locked::unmap_guarded(ptr, len, guard_pages),
+    }
+}
+
+/// Holds the lock of a block, see `AllocBlock::lock_usr_ptr`.
+pub struct BlockGuard {
+    block: *mut AllocBlock,
+}
+
+impl BlockGuard {
+    /// Gives up the guard of a block that was freed while it was held. Its lock went with it.
+    pub fn freed(self) {
+        core::mem::forget(self);
+    }
+}
+
+impl Drop for BlockGuard {
+    fn drop(&mut self) {
+        unsafe { (*self.block).release() };
+    }
+}
+
+/// Every live block, so operations like key rotation can reach all of them.
+static BLOCKS: SpinLock<Registry<AllocBlock>> = SpinLock::new(Registry::new());
+
+/// Held for the whole of a key rotation. Retiring the previous keys would otherwise drop a key
+/// that another rotation, still walking the blocks, had just moved some of them to.
+static ROTATION: SpinLock<()> = SpinLock::new(());
+
+/// The id given to the next block that is allocated.
+static NEXT_BLOCK_ID: AtomicU64 = AtomicU64::new(0);
+
+impl Registrable for AllocBlock {
+    fn links(&self) -> &Links<AllocBlock> {
+        &self.links
+    }
+
+    fn links_mut(&mut self) -> &mut Links<AllocBlock> {
+        &mut self.links
+    }
 }
 
 impl Weakable for AllocBlock {
     fn weak_exists(&self) -> bool {
-        self.weak_exists
+        self.weak_exists.count_ones() > 16
     }
 
     fn set_weak_exists(&mut self) {
-        self.weak_exists = true;
+        self.weak_exists = !0;
     }
 
     fn reset_weak_exists(&mut self) {
-        self.weak_exists = false;
+        self.weak_exists = 0;
     }
 }
 
@@ -375,13 +1697,42 @@ impl AllocBlock {
         let mut buffer_size = desired_size;
         for p in policies.iter().rev() {
             match p {
-                Policy::Redundancy(num_copies) => {
+                Policy::Redundancy(num_copies) | Policy::TypedRedundancy(num_copies, _) => {
                     buffer_size *= usize::try_from(*num_copies).unwrap()
                 }
-                Policy::ReedSolomon(n_ecc) => buffer_size += usize::try_from(*n_ecc).unwrap(),
+                // every copy carries its own checksum
+                Policy::CheckedRedundancy(num_copies) => {
+                    buffer_size =
+                        (buffer_size + CHECKED_COPY_CRC_LEN) * usize::try_from(*num_copies).unwrap()
+                }
+                Policy::ReedSolomon(..)
+                | Policy::InterleavedReedSolomon(..)
+                | Policy::DataOnlyReedSolomon(..)
+                | Policy::ReedSolomonField(..) => {
+                    // every codeword carries its own parity
+                    let layout = p.rs_layout(buffer_size);
+                    buffer_size += layout.n_codewords * layout.ecc_len
+                }
                 Policy::Encrypted => {
                     // nonce and ciphertext are stored together
-                    buffer_size += NONCE_LEN
+                    buffer_size += CRYPT_TRAILER_LEN
+                }
+                Policy::Authenticated => buffer_size += AEAD_TRAILER_LEN,
+                // one check byte per word
+                Policy::SecDed | Policy::DataOnlySecDed => {
+                    buffer_size += secded_words(buffer_size)
+                }
+                // the checksum is stored after the data, like the nonce
+                Policy::Checksum(width) => buffer_size += (*width / 8) as usize,
+                Policy::UnequalProtection(width, mask) => {
+                    let (width, n_bits) = uep_params(*width, *mask);
+                    buffer_size += UEP_COPIES * uep_stream_len(buffer_size, width, n_bits)
+                }
+                // the check bits of every word are stored after the data, like SEC-DED
+                Policy::LinearCode(id) => {
+                    let code = linear_code(*id);
+                    let n_words = (buffer_size + code.data_bytes() - 1) / code.data_bytes();
+                    buffer_size += n_words * code.check_bytes()
                 }
                 _ => (),
             }
@@ -398,34 +1749,85 @@ impl AllocBlock {
     /// * `policies` - The policies to be applied to the data. These are listed in the reverse order
     /// of how they will be applied to the data
     /// * `zeroed` - Is the data zeroed on initialization
+    ///
+    /// Zeroed data is protected right away, which fails if it cannot be encrypted. Encrypted
+    /// blocks are always zeroed, so they hold a key id and a valid tag before they are written.
     pub fn new<'a>(
         size: usize,
         policies: &[Policy; MAX_POLICIES],
         zeroed: bool,
-    ) -> WeakMut<'a, AllocBlock> {
+    ) -> Result<WeakMut<'a, AllocBlock>, ErError> {
+        AllocBlock::new_in(size, policies, zeroed, LockMode::Default)
+    }
+
+    /// Same as `new`, but `lock` picks whether the block is allocated from locked memory, which
+    /// is never swapped out or included in core dumps. By default, only encrypted blocks are.
+    ///
+    /// Fails with `ErError::LockFailed` if the memory cannot be locked.
+    pub fn new_in<'a>(
+        size: usize,
+        policies: &[Policy; MAX_POLICIES],
+        zeroed: bool,
+        lock: LockMode,
+    ) -> Result<WeakMut<'a, AllocBlock>, ErError> {
+        for policy in policies[1..].iter() {
+            if let Policy::SeparatedRedundancy(..) = policy {
+                panic!("Separated redundancy: Must be the outermost policy");
+            }
+        }
         let buffer_size: usize = AllocBlock::size_of(size, policies);
-        let layout =
-            Layout::from_size_align(buffer_size + core::mem::size_of::<AllocBlock>(), 16).unwrap();
+        let total_size = buffer_size + core::mem::size_of::<AllocBlock>();
+        let encrypted = policies.iter().any(|p| p.is_crypt());
+        let locked = lock.resolve(encrypted);
+        let zeroed = zeroed || encrypted;
 
         let block_ptr: *mut u8 = unsafe {
-            if zeroed {
-                alloc_zeroed(layout)
-            } else {
-                alloc(layout)
+            let layout = Layout::from_size_align(total_size, 16).unwrap();
+            match (locked, zeroed) {
+                (true, _) => locked::alloc(total_size, zeroed)?,
+                (false, true) => alloc_zeroed(layout),
+                (false, false) => alloc(layout),
             }
         };
         let block: &'a mut AllocBlock;
 
+        // Zeroed first, so the padding in the header is checksummed as zeros
+        unsafe { ptr::write_bytes(block_ptr, 0, core::mem::size_of::<AllocBlock>()) };
         block = unsafe { &mut *(block_ptr as *mut AllocBlock) };
-        block.buffer_size = buffer_size;
-        block.length = size;
-        block.policies = *policies;
-        block.weak_exists = false;
-
+        block.header.locked = locked;
+        block.header.buffer_size = buffer_size;
+        block.header.length = size;
+        block.header.policies = *policies;
+        block.weak_exists = 0;
+        // Held until its policies are applied, so walks of the registry leave it alone until then
+        block.in_use = AtomicU32::new(!0);
+        block.header.id = NEXT_BLOCK_ID.fetch_add(1, Ordering::Relaxed);
+        block.links = Links::default();
+        block.state = BLOCK_LIVE;
+        block.header.copies = [ptr::null_mut(); MAX_SEPARATED_COPIES - 1];
+
+        if let Some((n_separated, guard_pages)) = block.separation() {
+            for copy in 0..n_separated {
+                match unsafe { alloc_copy(buffer_size, guard_pages, locked) } {
+                    Ok(ptr) => block.header.copies[copy] = ptr,
+                    Err(e) => {
+                        block.drop_ref();
+                        return Err(e);
+                    }
+                }
+            }
+        }
+        // Only registered once the header is sealed, since walking the registry checks it
+        block.seal_header();
+        unsafe { BLOCKS.lock().insert(block) };
         if zeroed {
-            block.apply_policy();
+            if let Err(e) = block.apply_policy() {
+                block.drop_ref();
+                return Err(e);
+            }
         }
-        WeakMut::from(block)
+        block.release();
+        Ok(WeakMut::from(block))
     }
 
     /// Reallocates a block of the data on the heap like realloc. Internally, this calls the system
@@ -437,37 +1839,150 @@ impl AllocBlock {
     /// is larger to account for metadata that needs to be stored.
     /// * `new_policies` - The policies to be applied to the data. These are listed in the reverse order
     /// of how they will be applied to the data
+    /// * `lock` - Whether the new block is allocated from locked memory, as in `new_in`
+    ///
+    /// # Notes
+    /// The old policies are enforced one last time to recover the data, which is then protected
+    /// by the new policies in a new block. If the old data cannot be decrypted, the old block is
+    /// left as it is and the error is returned.
     pub fn renew<'a>(
         w: WeakMut<'a, AllocBlock>,
         new_size: usize,
         new_policies: &[Policy; MAX_POLICIES],
-    ) -> WeakMut<'a, AllocBlock> {
-        let new_buffer_size = AllocBlock::size_of(new_size, new_policies);
-        let layout =
-            Layout::from_size_align(new_buffer_size + core::mem::size_of::<AllocBlock>(), 16)
-                .unwrap();
+        lock: LockMode,
+    ) -> Result<WeakMut<'a, AllocBlock>, ErError> {
+        let old_block = w.get_ref_mut().expect("Called renew on invalid WeakMut");
+        old_block.correct_buffer()?;
+        old_block.decrypt_buffer()?;
+
+        let new_block = match AllocBlock::new_in(new_size, new_policies, false, lock) {
+            Ok(w) => w.get_ref_mut().expect("renew"),
+            Err(e) => {
+                old_block.encrypt_buffer()?;
+                return Err(e);
+            }
+        };
+        let kept = old_block.header.length.min(new_size);
+        new_block.data_slice()[..kept].copy_from_slice(&old_block.data_slice()[..kept]);
+        if let Err(e) = new_block.apply_policy() {
+            // Leave the old block as it was
+            new_block.drop_ref();
+            old_block.encrypt_buffer()?;
+            return Err(e);
+        }
+
+        old_block.drop_ref();
+        Ok(WeakMut::from(new_block))
+    }
+
+    /// Gets the block of a pointer that was handed out to the user. The pointer is checked
+    /// first, see `check_usr_ptr`, and so is the header, see `verify_header`.
+    pub fn from_usr_ptr<'a>(ptr: *const u8) -> Result<Weak<'a, AllocBlock>, ErError> {
+        let block = unsafe { &mut *AllocBlock::check_usr_ptr(ptr)? };
+        block.verify_header()?;
+        Ok(Weak::from(&*block))
+    }
+
+    /// Same as `from_usr_ptr`, but for changing the block.
+    pub fn from_usr_ptr_mut<'a>(ptr: *mut u8) -> Result<WeakMut<'a, AllocBlock>, ErError> {
+        let block = unsafe { &mut *AllocBlock::check_usr_ptr(ptr)? };
+        block.verify_header()?;
+        Ok(WeakMut::from(block))
+    }
+
+    /// Locks the block of a pointer that was handed out to the user until the guard is dropped,
+    /// waiting for any other thread that holds it. The pointer is checked first, see
+    /// `check_usr_ptr`.
+    ///
+    /// Every function that corrects or rewrites a block on behalf of the user holds the lock,
+    /// and so does the scrubber, so the two never work on a block at the same time.
+    pub fn lock_usr_ptr(ptr: *const u8) -> Result<BlockGuard, ErError> {
+        let block = unsafe { AllocBlock::check_usr_ptr(ptr)? };
+        while unsafe { !(*block).try_acquire() } {
+            core::hint::spin_loop();
+        }
+        Ok(BlockGuard { block })
+    }
+
+    /// Takes the lock of the block if no other thread holds it.
+    fn try_acquire(&self) -> bool {
+        let value = self.in_use.load(Ordering::Relaxed);
+        value.count_ones() <= 16
+            && self
+                .in_use
+                .compare_exchange(value, !0, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+    }
 
-        let new_block_ptr = unsafe { realloc(w.as_ptr() as *mut u8, layout, new_size) };
+    fn release(&self) {
+        self.in_use.store(0, Ordering::Release);
+    }
 
-        let new_block: &'a mut AllocBlock;
+    /// Gets the block of a pointer that was handed out to the user, if its state shows it is
+    /// one. A few flipped bits in the state are repaired.
+    ///
+    /// Fails with `ErError::AlreadyFreed` if the block was freed, and with
+    /// `ErError::InvalidPointer` if the pointer is null, was not returned by the allocator
+    /// (like a pointer into the middle of a block) or came from another allocator. Freed
+    /// blocks can only be recognised until their memory is reused, and memory that was
+    /// returned to the kernel cannot be read at all.
+    unsafe fn check_usr_ptr(ptr: *const u8) -> Result<*mut AllocBlock, ErError> {
+        // Blocks are 16 byte aligned, and the data starts right after the block
+        let addr = ptr as usize;
+        let header_len = core::mem::size_of::<AllocBlock>();
+        if addr < header_len || (addr - header_len) % 16 != 0 {
+            return Err(ErError::InvalidPointer);
+        }
 
-        new_block = unsafe { &mut *(new_block_ptr as *mut AllocBlock) };
-        new_block.buffer_size = new_buffer_size;
-        new_block.length = new_size;
-        new_block.policies = *new_policies;
-        new_block.weak_exists = false;
-        new_block.apply_policy();
-        WeakMut::from(new_block)
+        let block = (ptr as *mut AllocBlock).sub(1);
+        let state = ptr::addr_of_mut!((*block).state);
+        let value = ptr::read_volatile(state);
+        if (value ^ BLOCK_LIVE).count_ones() <= STATE_TOLERANCE {
+            if value != BLOCK_LIVE {
+                ptr::write_volatile(state, BLOCK_LIVE);
+            }
+            Ok(block)
+        } else if (value ^ BLOCK_FREED).count_ones() <= STATE_TOLERANCE {
+            Err(ErError::AlreadyFreed)
+        } else {
+            Err(ErError::InvalidPointer)
+        }
     }
 
-    pub fn from_usr_ptr<'a>(ptr: *const u8) -> Weak<'a, AllocBlock> {
-        let block = unsafe { &*(ptr as *const AllocBlock).sub(1) };
-        Weak::from(block)
+    /// Checksums the header and copies it to the backup. This must be done whenever the
+    /// header changes.
+    fn seal_header(&mut self) {
+        self.header_crc = unsafe { BlockHeader::checksum(&self.header) };
+        unsafe { ptr::copy_nonoverlapping(&self.header, &mut self.backup, 1) };
+        self.backup_crc = self.header_crc;
     }
 
-    pub fn from_usr_ptr_mut<'a>(ptr: *mut u8) -> WeakMut<'a, AllocBlock> {
-        let block = unsafe { &mut *(ptr as *mut AllocBlock).sub(1) };
-        WeakMut::from(block)
+    /// Checks the header against its checksum before anything trusts it. A header that does
+    /// not match is restored from the backup, and a backup that does not match is rewritten
+    /// from the header. Returns whether either had to be repaired.
+    ///
+    /// Fails with `ErError::CorruptHeader` if neither matches, in which case the block cannot
+    /// be used (or even freed, since its size is not known).
+    fn verify_header(&mut self) -> Result<bool, ErError> {
+        // Neither copy is touched as a `BlockHeader` until its checksum matches
+        let header = ptr::addr_of_mut!(self.header);
+        let backup = ptr::addr_of_mut!(self.backup);
+        let header_matches = unsafe { BlockHeader::checksum(header) } == self.header_crc;
+        let backup_matches = unsafe { BlockHeader::checksum(backup) } == self.backup_crc;
+        match (header_matches, backup_matches) {
+            (true, true) => Ok(false),
+            (true, false) => {
+                unsafe { ptr::copy_nonoverlapping(header, backup, 1) };
+                self.backup_crc = self.header_crc;
+                Ok(true)
+            }
+            (false, true) => {
+                unsafe { ptr::copy_nonoverlapping(backup, header, 1) };
+                self.header_crc = self.backup_crc;
+                Ok(true)
+            }
+            (false, false) => Err(ErError::CorruptHeader),
+        }
     }
 
     pub fn drop<'a>(w: WeakMut<'a, AllocBlock>) {
@@ -476,23 +1991,73 @@ impl AllocBlock {
             .drop_ref();
     }
 
+    /// Frees the block. Its data, copies and header are poisoned first, which also keeps the
+    /// plaintext windows, copies and parity of encrypted blocks from lingering in freed memory,
+    /// and it is marked as freed so later uses of its pointer are caught.
     fn drop_ref(&mut self) {
-        let buffer_size: usize = AllocBlock::size_of(self.length, &self.policies);
-        let layout =
-            Layout::from_size_align(buffer_size + core::mem::size_of::<AllocBlock>(), 16).unwrap();
+        unsafe { BLOCKS.lock().remove(self) };
+
+        zeroize::fill(self.buffer_mut(), FREED_POISON);
+        if let Some((n_separated, guard_pages)) = self.separation() {
+            // Copies that were never allocated, if allocating the block failed part way, are null
+            for copy in (0..n_separated).filter(|&copy| !self.header.copies[copy].is_null()) {
+                let len = self.header.buffer_size;
+                zeroize::fill(unsafe { core::slice::from_raw_parts_mut(self.separated_copy(copy), len) }, FREED_POISON);
+                unsafe { dealloc_copy(self.header.copies[copy], self.header.buffer_size, guard_pages, self.header.locked) };
+            }
+        }
+
+        let buffer_size: usize = AllocBlock::size_of(self.header.length, &self.header.policies);
+        let total_size = buffer_size + core::mem::size_of::<AllocBlock>();
+        let locked = self.header.locked;
 
         unsafe {
             let ptr: *mut u8 = transmute(self as *mut AllocBlock);
-            dealloc(ptr, layout)
+            // Everything but the state, which is the last field
+            let header_len = core::mem::size_of::<AllocBlock>() - core::mem::size_of::<u64>();
+            zeroize::fill(core::slice::from_raw_parts_mut(ptr, header_len), FREED_POISON);
+            ptr::write_volatile(ptr.add(header_len) as *mut u64, BLOCK_FREED);
+            match locked {
+                true => locked::dealloc(ptr, total_size),
+                false => dealloc(ptr, Layout::from_size_align(total_size, 16).unwrap()),
+            }
         };
     }
 
+    /// The number of copies in allocations of their own and the number of guard pages around
+    /// each, if the block has any.
+    fn separation(&self) -> Option<(usize, usize)> {
+        match self.header.policies[0] {
+            Policy::SeparatedRedundancy(n_copies, guard_pages) => {
+                if n_copies == 0 || n_copies as usize > MAX_SEPARATED_COPIES {
+                    panic!(
+                        "Separated redundancy: The number of copies must be between 1 and {}",
+                        MAX_SEPARATED_COPIES
+                    );
+                }
+                Some((n_copies as usize - 1, guard_pages as usize))
+            }
+            _ => None,
+        }
+    }
+
+    /// Gets one of the copies of the buffer that live in allocations of their own. Each is
+    /// `buffer_size` bytes long.
+    fn separated_copy(&self, copy: usize) -> *mut u8 {
+        self.header.copies[copy]
+    }
+
     /// Gets a slice the represents the total data + error correct bytes that were allocated. (This should only be used internally)
     fn buffer(&self) -> &mut [u8] {
-        unsafe { core::slice::from_raw_parts_mut(self.ptr(), self.buffer_size) }
+        unsafe { core::slice::from_raw_parts_mut(self.ptr(), self.header.buffer_size) }
     }
     fn buffer_mut(&mut self) -> &mut [u8] {
-        unsafe { core::slice::from_raw_parts_mut(self.ptr(), self.buffer_size) }
+        unsafe { core::slice::from_raw_parts_mut(self.ptr(), self.header.buffer_size) }
+    }
+
+    /// The amount of data allocated, as specified by the user.
+    pub fn length_ffi<'a>(mut w: Weak<'a, AllocBlock>) -> usize {
+        w.get_ref().expect("length_ffi").header.length
     }
 
     pub fn data_slice_ffi<'a>(w: WeakMut<'a, AllocBlock>) -> &mut [u8] {
@@ -501,119 +2066,262 @@ impl AllocBlock {
 
     /// Gets a slice representing the bytes that the user wanted
     fn data_slice(&self) -> &mut [u8] {
-        unsafe { core::slice::from_raw_parts_mut(self.ptr(), self.length) }
+        unsafe { core::slice::from_raw_parts_mut(self.ptr(), self.header.length) }
     }
-    pub fn correct_buffer_ffi<'a>(w: WeakMut<'a, AllocBlock>) -> u32 {
+    pub fn correct_buffer_ffi<'a>(w: WeakMut<'a, AllocBlock>) -> Result<u32, ErError> {
         w.get_ref_mut()
             .expect("correct_buffer_ffi")
             .correct_buffer()
     }
 
-    pub fn encrypt_buffer_ffi<'a>(w: WeakMut<'a, AllocBlock>) {
+    pub fn correct_buffer_observed_ffi<'a>(
+        w: WeakMut<'a, AllocBlock>,
+        observer: &mut dyn CorrectionObserver,
+    ) -> Result<u32, ErError> {
         w.get_ref_mut()
-            .expect("encrypt_buffer_ffi")
-            .encrypt_buffer()
+            .expect("correct_buffer_observed_ffi")
+            .correct_buffer_observed(observer)
     }
 
-    pub fn decrypt_buffer_ffi<'a>(w: WeakMut<'a, AllocBlock>) {
+    pub fn decrypt_buffer_ffi<'a>(w: WeakMut<'a, AllocBlock>) -> Result<(), ErError> {
         w.get_ref_mut()
             .expect("decrypt_buffer_ffi")
             .decrypt_buffer()
     }
 
-    fn encrypt_buffer(&mut self) {
-        let mut buffer = self.buffer();
+    /// Finds the slice that the encryption policy applies to (the ciphertext and the nonce)
+    /// by descending through the policies wrapped around it. Only the first copy is returned
+    /// when the data is redundant.
+    fn crypt_buffer(&self) -> Option<(Policy, &mut [u8])> {
+        self.crypt_region(self.buffer())
+    }
 
-        match self.policies.iter().position(|&pol| pol.is_red()) {
-            Some(idx) => {
-                buffer = self.policies[idx].get_data_mut(buffer);
+    /// Same as `crypt_buffer`, but for a buffer laid out like this block's.
+    fn crypt_region<'b>(&self, mut buffer: &'b mut [u8]) -> Option<(Policy, &'b mut [u8])> {
+        for policy in self.header.policies.iter() {
+            match policy {
+                Policy::Nil => return None,
+                Policy::Encrypted | Policy::Authenticated => return Some((*policy, buffer)),
+                _ => buffer = policy.get_data_mut(buffer),
             }
-            None => (),
         }
+        None
+    }
 
-        match self.policies.iter().position(|&pol| pol.is_rs()) {
-            Some(idx) => {
-                buffer = self.policies[idx].get_data_mut(buffer);
-            }
-            None => (),
-        }
+    pub fn read_ffi<'a>(
+        w: WeakMut<'a, AllocBlock>,
+        dest: &mut [u8],
+        offset: usize,
+    ) -> Result<(), ErError> {
+        w.get_ref_mut().expect("read_ffi").read(dest, offset)
+    }
 
-        match self.policies.iter().position(|&pol| pol.is_crypt()) {
-            Some(idx) => {
-                let key = GenericArray::from_slice(KEY);
-                let nonce = GenericArray::from_slice(NONCE);
-                let mut cipher = Aes128Ctr::new(&key, &nonce);
-                let (mut data, err) = self.policies[idx].split_buffer_mut(buffer);
-                cipher.apply_keystream(&mut data);
-                err.copy_from_slice(NONCE);
+    /// Reads `dest.len()` bytes of the data, starting `offset` bytes in, into `dest`. Encrypted
+    /// data is decrypted on the way out, so the block itself always stays ciphertext.
+    /// This should be done after the buffer is corrected.
+    fn read(&self, dest: &mut [u8], offset: usize) -> Result<(), ErError> {
+        match self.crypt_buffer() {
+            Some((policy, buffer)) => policy.decrypt_range(buffer, self.header.id, offset, dest),
+            None => {
+                dest.copy_from_slice(&self.data_slice()[offset..offset + dest.len()]);
+                Ok(())
             }
-            None => (),
         }
     }
 
-    fn decrypt_buffer(&mut self) {
-        let mut buffer = self.buffer();
+    /// Re-encrypts data that was decrypted with `decrypt_buffer`.
+    ///
+    /// The nonce already stored in the trailer is reused, so the ciphertext comes out exactly as
+    /// it was and any error correction applied over it stays valid. Writes must use
+    /// `apply_policy` instead, which picks a fresh nonce.
+    fn encrypt_buffer(&mut self) -> Result<(), ErError> {
+        match self.crypt_buffer() {
+            Some((policy, buffer)) => policy.encrypt(buffer, self.header.id),
+            None => Ok(()),
+        }
+    }
 
-        match self.policies.iter().position(|&pol| pol.is_red()) {
-            Some(idx) => {
-                buffer = self.policies[idx].get_data_mut(buffer);
-            }
-            None => (),
+    /// Decrypts the data in place. This should be done after the buffer is corrected, since
+    /// authenticated encryption rejects any remaining error.
+    fn decrypt_buffer(&mut self) -> Result<(), ErError> {
+        match self.crypt_buffer() {
+            Some((policy, buffer)) => policy.decrypt(buffer, self.header.id),
+            None => Ok(()),
         }
+    }
 
-        match self.policies.iter().position(|&pol| pol.is_rs()) {
-            Some(idx) => {
-                buffer = self.policies[idx].get_data_mut(buffer);
-            }
-            None => (),
+    /// Moves the block to the current key. The buffer is corrected and decrypted under the key
+    /// it was written with, then encrypted and protected again under the current key with a
+    /// fresh nonce. Returns whether the block had to be moved.
+    ///
+    /// The new contents are built in a scratch buffer and copied over the old ones in one go,
+    /// so the ciphertext, nonce, key id and error correction bits in the block always belong
+    /// to the same key. If the block cannot be decrypted it is left untouched.
+    fn rotate_key(&mut self) -> Result<bool, ErError> {
+        if self.crypt_buffer().is_none() {
+            return Ok(false);
         }
+        let buffer = self.buffer();
+        let mut scratch = Scratch::from(&buffer[..]);
+        self.correct_bits_helper(0, &mut scratch, None, &mut ())?;
 
-        match self.policies.iter().position(|&pol| pol.is_crypt()) {
-            Some(idx) => {
-                let key = GenericArray::from_slice(KEY);
-                let (mut ciphertext, _nonce) = self.policies[idx].split_buffer_mut(buffer);
-                let nonce = GenericArray::from_slice(&_nonce);
-                let mut cipher = Aes128Ctr::new(&key, &nonce);
-                cipher.apply_keystream(&mut ciphertext);
+        match self.crypt_region(&mut scratch) {
+            Some((policy, region)) => {
+                if policy.key_id(region) == crypto::current_key_id() {
+                    return Ok(false);
+                }
+                policy.decrypt(region, self.header.id)?;
             }
-            None => (),
+            None => return Ok(false),
         }
+        self.apply_policy_helper(0, &mut scratch)?;
+
+        buffer.copy_from_slice(&scratch);
+        Ok(true)
     }
 
     /// The public function used to correct the buffer from potential SEU events. This should be used before
     /// any read operations.
     /// When correcting data, first Reed Solomon is used (ie a block is corrected). If RS fails, then
     /// Redundancy is used to take a vote of corresponding bits in each of the redundant blocks.
-    fn correct_buffer(&mut self) -> u32 {
+    fn correct_buffer(&mut self) -> Result<u32, ErError> {
+        self.correct_buffer_observed(&mut ())
+    }
+
+    /// Same as `correct_buffer`, but also reports the per-codeword results to `observer`.
+    fn correct_buffer_observed(
+        &mut self,
+        observer: &mut dyn CorrectionObserver,
+    ) -> Result<u32, ErError> {
         let buffer = self.buffer();
-        self.correct_bits_helper(0, buffer)
+        self.correct_bits_helper(0, buffer, None, observer)
     }
 
     /// This is a helper function for correct buffer that recurisively is used to apply each policy.
     /// Note that this function is more expensive than is corrupted since it corrects for every branch
-    /// of the redundancy.
-    fn correct_bits_helper(&self, index: usize, full_buffer: &mut [u8]) -> u32 {
+    /// of the redundancy, which `correct_copies` votes on.
+    ///
+    /// `suspects` marks the bytes of `full_buffer` that are known to be suspect, if any.
+    fn correct_bits_helper(
+        &self,
+        index: usize,
+        full_buffer: &mut [u8],
+        suspects: Option<&[bool]>,
+        observer: &mut dyn CorrectionObserver,
+    ) -> Result<u32, ErError> {
         let corrected_bits = match index == MAX_POLICIES {
-            true => return 0,
-            false => match self.policies[index] {
-                Policy::Nil | Policy::Encrypted => return 0,
-                Policy::Redundancy(n_copies) => {
-                    if full_buffer.len() % (n_copies as usize) != 0 {
-                        panic!("Redundancy: Size of buffer is not a multiple of the data size");
+            true => return Ok(0),
+            false => match self.header.policies[index] {
+                Policy::Nil | Policy::Encrypted | Policy::Authenticated => return Ok(0),
+                Policy::Redundancy(..) | Policy::CheckedRedundancy(..) | Policy::TypedRedundancy(..) => {
+                    return self.correct_copies(index, self.header.policies[index], full_buffer, observer);
+                }
+                Policy::SeparatedRedundancy(n_copies, _) => {
+                    // The copies are gathered back to back, voted on like Redundancy and put back
+                    let copy_len = full_buffer.len();
+                    let mut gathered = Scratch::zeroed(copy_len * n_copies as usize);
+                    let mut slices = gathered.chunks_exact_mut(copy_len);
+                    slices.next().unwrap().copy_from_slice(full_buffer);
+                    for (copy, slice) in slices.enumerate() {
+                        slice.copy_from_slice(unsafe { core::slice::from_raw_parts(self.separated_copy(copy), copy_len) });
                     }
-                    let data_len = full_buffer.len() / (n_copies as usize);
 
-                    full_buffer
-                        .chunks_exact_mut(data_len)
-                        .map(|slice| self.correct_bits_helper(index + 1, slice))
-                        .sum()
+                    let result =
+                        self.correct_copies(index, Policy::Redundancy(n_copies), &mut gathered, observer);
+                    let mut slices = gathered.chunks_exact(copy_len);
+                    full_buffer.copy_from_slice(slices.next().unwrap());
+                    for (copy, slice) in slices.enumerate() {
+                        unsafe { core::slice::from_raw_parts_mut(self.separated_copy(copy), copy_len) }
+                            .copy_from_slice(slice);
+                    }
+                    return result;
+                }
+                policy => {
+                    let data = policy.get_data_mut(full_buffer);
+                    // A checksum is stored after the data, so the suspects still line up
+                    let suspects = match policy {
+                        Policy::Checksum(_) => suspects.map(|suspects| &suspects[..data.len()]),
+                        _ => None,
+                    };
+                    self.correct_bits_helper(index + 1, data, suspects, observer)?
                 }
-                _ => self
-                    .correct_bits_helper(index + 1, self.policies[index].get_data_mut(full_buffer)),
             },
         };
 
-        corrected_bits + self.policies[index].correct_buffer(full_buffer)
+        Ok(corrected_bits + self.header.policies[index].correct_buffer(full_buffer, suspects, observer)?)
+    }
+
+    /// Corrects the copies in `full_buffer` with the policies after `index`, then takes a vote
+    /// between them with `vote`, which is one of the redundancy policies.
+    ///
+    /// Copies that the inner policies cannot correct are left to the vote, and are only reported
+    /// as uncorrectable if the vote does not fix them either. When the copies are protected by
+    /// Reed Solomon, the bytes where they disagree are given to it as erasures.
+    fn correct_copies(
+        &self,
+        index: usize,
+        vote: Policy,
+        full_buffer: &mut [u8],
+        observer: &mut dyn CorrectionObserver,
+    ) -> Result<u32, ErError> {
+        let n_copies = match vote {
+            Policy::Redundancy(n_copies)
+            | Policy::CheckedRedundancy(n_copies)
+            | Policy::TypedRedundancy(n_copies, _) => n_copies,
+            _ => panic!("Redundancy: Not a redundancy policy"),
+        };
+
+        // Checked copies are followed by their checksum, which is not for the inner
+        // policies
+        let (copy_len, data_len) = match vote {
+            Policy::CheckedRedundancy(_) => {
+                checked_copy_len(full_buffer.len(), n_copies as usize)
+            }
+            _ => {
+                if full_buffer.len() % (n_copies as usize) != 0 {
+                    panic!("Redundancy: Size of buffer is not a multiple of the data size");
+                }
+                let data_len = full_buffer.len() / (n_copies as usize);
+                (data_len, data_len)
+            }
+        };
+
+        // At least one copy is wrong wherever the copies disagree
+        let code = self.header.policies[index + 1..]
+            .iter()
+            .find(|pol| !matches!(pol, Policy::Checksum(_)));
+        let disagreements: Option<Vec<bool>> = match code.is_some_and(|pol| pol.is_rs()) {
+            true => Some(
+                (0..data_len)
+                    .map(|byte| {
+                        (1..n_copies as usize).any(|copy| {
+                            full_buffer[copy * copy_len + byte] != full_buffer[byte]
+                        })
+                    })
+                    .collect(),
+            ),
+            false => None,
+        };
+
+        let mut corrected_bits = 0;
+        let mut uncorrectable = false;
+        for slice in full_buffer.chunks_exact_mut(copy_len) {
+            match self.correct_bits_helper(
+                index + 1,
+                &mut slice[..data_len],
+                disagreements.as_deref(),
+                observer,
+            ) {
+                Ok(n) => corrected_bits += n,
+                Err(_) => uncorrectable = true,
+            }
+        }
+
+        let voted = vote.correct_buffer(full_buffer, None, observer)?;
+        if uncorrectable && self.is_corrupted_helper(index + 1, &full_buffer[..data_len]) {
+            return Err(ErError::Uncorrectable);
+        }
+        Ok(corrected_bits + voted)
     }
 
     /// Determines if the buffer is corrupted. When possible, use this function as opposed to correct_buffer
@@ -626,53 +2334,309 @@ impl AllocBlock {
     fn is_corrupted_helper(&self, index: usize, full_buffer: &[u8]) -> bool {
         let corrected_bits = match index == MAX_POLICIES {
             true => return false,
-            false => match self.policies[index] {
-                Policy::Nil | Policy::Encrypted => return false,
+            false => match self.header.policies[index] {
+                Policy::Nil | Policy::Encrypted | Policy::Authenticated => return false,
+                Policy::SeparatedRedundancy(..) => {
+                    let (n_separated, _) = self.separation().unwrap();
+                    return self.is_corrupted_helper(index + 1, full_buffer)
+                        || (0..n_separated).any(|copy| {
+                            let separated =
+                                unsafe { core::slice::from_raw_parts(self.separated_copy(copy), full_buffer.len()) };
+                            separated != full_buffer
+                        });
+                }
                 _ => {
-                    self.is_corrupted_helper(index + 1, self.policies[index].get_data(full_buffer))
+                    self.is_corrupted_helper(index + 1, self.header.policies[index].get_data(full_buffer))
                 }
             },
         };
 
-        corrected_bits || self.policies[index].is_corrupted(full_buffer)
+        corrected_bits || self.header.policies[index].is_corrupted(full_buffer)
     }
 
     /// Applies the policy list to the buffer of data assuming that the
     /// data in the first data_length bits are correct.
     /// This should be used after any write operations to provide error protection against those bits.
-    fn apply_policy(&self) {
+    fn apply_policy(&self) -> Result<(), ErError> {
         let buffer = self.buffer();
-        self.apply_policy_helper(0, buffer);
+        self.apply_policy_helper(0, buffer)
     }
-    pub fn apply_policy_ffi<'a>(w: WeakMut<'a, AllocBlock>) {
+    pub fn apply_policy_ffi<'a>(w: WeakMut<'a, AllocBlock>) -> Result<(), ErError> {
         w.downgrade()
             .get_ref()
             .expect("apply policy ffi")
-            .apply_policy();
+            .apply_policy()
     }
 
     /// Helper function that applies the policy at the given index.
-    fn apply_policy_helper(&self, index: usize, full_buffer: &mut [u8]) {
+    fn apply_policy_helper(&self, index: usize, full_buffer: &mut [u8]) -> Result<(), ErError> {
         match index == MAX_POLICIES {
-            true => return,
-            false => match self.policies[index] {
-                Policy::Nil => return,
+            true => return Ok(()),
+            false => match self.header.policies[index] {
+                Policy::Nil => return Ok(()),
                 _ => self
-                    .apply_policy_helper(index + 1, self.policies[index].get_data_mut(full_buffer)),
+                    .apply_policy_helper(index + 1, self.header.policies[index].get_data_mut(full_buffer))?,
             },
         };
 
-        self.policies[index].apply_policy(full_buffer)
+        match self.header.policies[index] {
+            // The policy cannot reach the copies in allocations of their own
+            Policy::SeparatedRedundancy(..) => {
+                let (n_separated, _) = self.separation().unwrap();
+                for copy in 0..n_separated {
+                    unsafe { core::slice::from_raw_parts_mut(self.separated_copy(copy), full_buffer.len()) }
+                        .copy_from_slice(full_buffer);
+                }
+                Ok(())
+            }
+            policy => policy.apply_policy(full_buffer, self.header.id),
+        }
+    }
+}
+
+/// The most blocks `lock_blocks` locks at a time, so threads that need one of them do not wait
+/// long.
+const LOCK_BATCH: usize = 32;
+
+/// Blocks locked by `lock_blocks`.
+struct LockedBlocks {
+    /// The blocks that were locked, most recently allocated first.
+    guards: Vec<BlockGuard>,
+    /// The id of the last block that was reached, if some of the blocks it was after were left.
+    left_below: Option<u64>,
+    /// `ErError::CorruptRegistry` if some blocks could not be reached.
+    walked: Result<u32, ErError>,
+}
+
+/// Locks live blocks so they can be worked on once the registry is released. Locked blocks
+/// cannot be freed, so they stay valid until their guard is dropped.
+///
+/// Goes through the blocks `wanted` accepts, most recently allocated first, and locks each
+/// one until `more` returns false for the last one locked or `LOCK_BATCH` blocks are locked.
+/// Waiting for a block with the registry locked would deadlock with `er_free`, which locks the
+/// block before the registry, so blocks held by another thread are passed to `busy` instead.
+fn lock_blocks<W, B, M>(mut wanted: W, mut busy: B, mut more: M) -> LockedBlocks
+where
+    W: FnMut(&AllocBlock) -> bool,
+    B: FnMut(&AllocBlock),
+    M: FnMut(&AllocBlock) -> bool,
+{
+    let mut guards = Vec::with_capacity(LOCK_BATCH);
+    let mut last = None;
+    let mut full = false;
+    let mut left_below = None;
+    let walked = BLOCKS.lock().for_each(|block_ptr| {
+        let block = unsafe { &*block_ptr };
+        if left_below.is_some() || !wanted(block) {
+            return;
+        }
+        if full {
+            left_below = last;
+            return;
+        }
+        last = Some(block.header.id);
+        if !block.try_acquire() {
+            busy(block);
+            return;
+        }
+        guards.push(BlockGuard { block: block_ptr });
+        full = !more(block) || guards.len() == LOCK_BATCH;
+    });
+    LockedBlocks { guards, left_below, walked }
+}
+
+/// Makes the key from `provider` the encryption key and moves every live encrypted block to it.
+/// Returns the number of blocks that were re-encrypted.
+///
+/// The keys being replaced are only forgotten once every block has been moved, so if a block
+/// cannot be moved (it fails authentication) the error is returned and that block stays
+/// readable under its old key, through any number of rotations, until one moves it.
+///
+/// Each block is locked while it is moved, and blocks held by other threads are waited for.
+/// Rotations take turns, so one started meanwhile waits until this one is done.
+pub fn set_key_provider(mut provider: Box<dyn KeyProvider>) -> Result<usize, ErError> {
+    let _rotation = ROTATION.lock();
+    // A provider that cannot supply its key would strand every block, so it is refused up front
+    let mut probe = [0u8; KEY_LEN];
+    let probed = provider.key(&mut probe);
+    zeroize::wipe(&mut probe);
+    probed?;
+
+    // Blocks allocated from here on are encrypted under the new key
+    crypto::install_provider(provider);
+
+    let mut rotated = 0;
+    let mut result = Ok(());
+    let mut walked = Ok(0);
+    // Every block the first time, then the blocks other threads held
+    let mut waiting = Vec::new();
+    loop {
+        let mut below = None;
+        let mut busy = Vec::new();
+        loop {
+            let locked = lock_blocks(
+                |block| {
+                    !matches!(below, Some(id) if block.header.id >= id)
+                        && (waiting.is_empty() || waiting.contains(&block.header.id))
+                },
+                |block| busy.push(block.header.id),
+                |_| true,
+            );
+            // Blocks that cannot be reached would be stranded by retiring the key
+            if locked.walked.is_err() {
+                walked = locked.walked;
+            }
+            for guard in locked.guards {
+                let block = unsafe { &mut *guard.block };
+                match block.verify_header().and_then(|_| block.rotate_key()) {
+                    Ok(true) => rotated += 1,
+                    Ok(false) => (),
+                    Err(e) => {
+                        if result.is_ok() {
+                            result = Err(e);
+                        }
+                    }
+                }
+            }
+            match locked.left_below {
+                Some(id) => below = Some(id),
+                None => break,
+            }
+        }
+
+        // Blocks held by other threads are tried again until they are let go or freed
+        if busy.is_empty() {
+            break;
+        }
+        waiting = busy;
+        core::hint::spin_loop();
+    }
+    walked?;
+    result?;
+
+    crypto::retire_previous_keys();
+    Ok(rotated)
+}
+
+/// A live block, as seen by `for_each_block`.
+#[derive(Debug, Copy, Clone)]
+pub struct BlockInfo {
+    /// The pointer that was handed out for the block.
+    pub ptr: *mut u8,
+    /// The amount of data allocated, as specified by the user.
+    pub length: usize,
+    /// The size of the data and everything its policies added.
+    pub buffer_size: usize,
+    /// The id of the block, which is unique for the lifetime of the process.
+    pub id: u64,
+}
+
+/// Calls `f` on every live block, most recently allocated first, until it returns false.
+/// The blocks are listed first and `f` is called once the registry is released, so `f` may
+/// allocate and free blocks, and blocks freed by then are still passed to it.
+///
+/// Broken links in the registry are repaired on the way. Blocks whose header cannot be
+/// repaired are skipped. Returns the number of blocks `f` was called on, or
+/// `ErError::CorruptRegistry` if some blocks could not be reached.
+pub fn for_each_block<F>(mut f: F) -> Result<usize, ErError>
+where
+    F: FnMut(&BlockInfo) -> bool,
+{
+    let mut blocks = Vec::new();
+    let walked = BLOCKS.lock().for_each(|block| {
+        let block = unsafe { &mut *block };
+        if block.verify_header().is_ok() {
+            blocks.push(BlockInfo {
+                ptr: block.ptr(),
+                length: block.header.length,
+                buffer_size: block.header.buffer_size,
+                id: block.header.id,
+            });
+        }
+    });
+
+    let mut visited = 0;
+    for info in blocks.iter() {
+        visited += 1;
+        if !f(info) {
+            break;
+        }
+    }
+    walked?;
+    Ok(visited)
+}
+
+/// Corrects live blocks, in the order `for_each_block` visits them, so errors are found before
+/// they pile up in blocks that are never read. Only blocks with an id below `below` are
+/// scrubbed, and once they add up to more than `budget` bytes (0 for no limit) the rest are
+/// left for later. Blocks held by another thread are skipped rather than waited for.
+///
+/// Returns what was done, and the id to pass as `below` to carry on where this left off, or
+/// `None` if every block was reached.
+pub fn scrub_blocks(mut below: Option<u64>, budget: usize) -> (ScrubStats, Option<u64>) {
+    let mut stats = ScrubStats::default();
+    loop {
+        let mut busy = 0;
+        let mut locked_bytes = stats.bytes;
+        // Blocks cut off from a corrupted registry cannot be reached, so they are left as they are
+        let locked = lock_blocks(
+            |block| !matches!(below, Some(id) if block.header.id >= id),
+            |_| busy += 1,
+            // Only a guess until the header is verified, so no more blocks are locked than needed
+            |block| {
+                locked_bytes += block.header.buffer_size as u64;
+                budget == 0 || locked_bytes < budget as u64
+            },
+        );
+        stats.busy += busy;
+
+        for guard in locked.guards {
+            let block = unsafe { &mut *guard.block };
+            if block.verify_header().is_err() {
+                stats.uncorrectable += 1;
+                continue;
+            }
+            let copies = block.separation().map_or(1, |(n_separated, _)| 1 + n_separated);
+            let size = (block.header.buffer_size * copies) as u64;
+            if budget != 0 && stats.bytes != 0 && stats.bytes + size > budget as u64 {
+                return (stats, Some(block.header.id + 1));
+            }
+            match block.correct_buffer() {
+                Ok(corrected) => stats.corrected += corrected as u64,
+                Err(_) => stats.uncorrectable += 1,
+            }
+            stats.blocks += 1;
+            stats.bytes += size;
+        }
+
+        match locked.left_below {
+            Some(id) if budget == 0 || stats.bytes < budget as u64 => below = Some(id),
+            resume => return (stats, resume),
+        }
     }
 }
 
+/// Same as `set_key_provider` with a key kept in memory.
+pub fn set_key(key: &[u8; KEY_LEN]) -> Result<usize, ErError> {
+    set_key_provider(Box::new(MemoryKeyProvider::new(key)))
+}
+
+/// Same as `set_key` with a fresh random key.
+pub fn rotate_key() -> Result<usize, ErError> {
+    set_key_provider(Box::new(MemoryKeyProvider::random()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    // Key rotation and `for_each_block` touch every live block, so tests that look at
+    // ciphertext or corrupt the metadata of a block take turns
+    static CRYPTO_TESTS: SpinLock<()> = SpinLock::new(());
+
     #[test]
     fn redundancy_check() {
-        let block = AllocBlock::new(1, &[Policy::Redundancy(3), Policy::Nil, Policy::Nil], false);
+        let block = AllocBlock::new(1, &[Policy::Redundancy(3), Policy::Nil, Policy::Nil, Policy::Nil], false).unwrap();
 
         // Create errors
         // unsafe {
@@ -686,7 +2650,7 @@ mod tests {
         slice[1] = 0b1010;
         slice[2] = 0b0000;
         assert_eq!(block_ref.is_corrupted(), true);
-        assert_eq!(block_ref.correct_buffer(), 4);
+        assert_eq!(block_ref.correct_buffer(), Ok(4));
         assert_eq!(block_ref.is_corrupted(), false);
         let slice = unsafe { block_ref.buffer() };
         for idx in 0..3 {
@@ -700,20 +2664,1089 @@ mod tests {
     fn fec_check() {
         let block = AllocBlock::new(
             1,
-            &[Policy::ReedSolomon(3), Policy::Nil, Policy::Nil],
+            &[Policy::ReedSolomon(3), Policy::Nil, Policy::Nil, Policy::Nil],
             false,
-        );
+        ).unwrap();
 
         let block_ref = block.get_ref_mut().unwrap();
         let slice = unsafe { block_ref.buffer() };
         slice[0] = 0b1111;
-        block_ref.apply_policy();
+        block_ref.apply_policy().unwrap();
         let slice = unsafe { block_ref.buffer() };
         slice[0] = 0b1011;
         assert_eq!(block_ref.is_corrupted(), true);
-        assert_eq!(block_ref.correct_buffer(), 1);
+        assert_eq!(block_ref.correct_buffer(), Ok(1));
         assert_eq!(block_ref.is_corrupted(), false);
         let slice = unsafe { block_ref.buffer() };
         assert_eq!(slice[0], 0b1111 as u8);
     }
+
+    #[test]
+    fn interleaved_fec_check() {
+        // The data length is recovered from the buffer length whatever the number of codewords
+        for depth in [1, 3, 8, 300].iter() {
+            for n_ecc in [1, 4, 32].iter() {
+                let policy = Policy::InterleavedReedSolomon(*n_ecc, *depth);
+                for size in (1..40).chain(740..800) {
+                    let policies = [policy, Policy::Nil, Policy::Nil, Policy::Nil];
+                    let buffer_size = AllocBlock::size_of(size, &policies);
+                    assert_eq!(policy.rs_split_len(buffer_size), size);
+                }
+            }
+        }
+
+        let burst = |policy: Policy, bytes: Range<usize>| {
+            let block = AllocBlock::new(16, &[policy, Policy::Nil, Policy::Nil, Policy::Nil], false).unwrap();
+            let block_ref = block.get_ref_mut().unwrap();
+            for idx in 0..16 {
+                block_ref.data_slice()[idx] = idx as u8;
+            }
+            block_ref.apply_policy().unwrap();
+            for byte in bytes {
+                block_ref.buffer()[byte] ^= 0xff;
+            }
+            let corrected = block_ref.correct_buffer();
+            let intact = (0..16).all(|idx| block_ref.data_slice()[idx] == idx as u8);
+            block_ref.drop_ref();
+            (corrected, intact)
+        };
+
+        // Spread over four codewords, a burst of four bytes is one error per codeword,
+        // in the data or in the parity
+        assert_eq!(burst(Policy::InterleavedReedSolomon(2, 4), 5..9), (Ok(4), true));
+        assert_eq!(burst(Policy::InterleavedReedSolomon(2, 4), 18..22), (Ok(4), true));
+        assert_eq!(burst(Policy::ReedSolomon(2), 5..9), (Ok(0), false));
+    }
+
+    #[test]
+    fn secded_policy_check() {
+        // 20 bytes are 3 words, the last one short, with one check byte each
+        let block = AllocBlock::new(20, &[Policy::SecDed, Policy::Nil, Policy::Nil, Policy::Nil], false).unwrap();
+        let block_ref = block.get_ref_mut().unwrap();
+        assert_eq!(block_ref.header.buffer_size, 23);
+        for idx in 0..20 {
+            block_ref.data_slice()[idx] = (idx * 37) as u8;
+        }
+        block_ref.apply_policy().unwrap();
+
+        // A flipped data bit in each word, then a flipped Hamming bit and overall parity bit
+        for (byte, mask) in [(0, 0x01), (13, 0x80), (19, 0x08), (20, 0x04), (22, 0x80)].iter() {
+            block_ref.buffer()[*byte] ^= mask;
+            assert_eq!(block_ref.is_corrupted(), true);
+            assert_eq!(block_ref.correct_buffer(), Ok(1));
+            assert_eq!(block_ref.is_corrupted(), false);
+        }
+        for idx in 0..20 {
+            assert_eq!(block_ref.data_slice()[idx], (idx * 37) as u8);
+        }
+
+        // Two flips in one word are detected but not corrected
+        block_ref.buffer()[9] ^= 0x11;
+        let mut counts = Counts { errors: [None; 8], n_codewords: 0 };
+        assert_eq!(block_ref.correct_buffer_observed(&mut counts), Err(ErError::Uncorrectable));
+        assert_eq!(&counts.errors[..3], &[Some(0), None, Some(0)]);
+    }
+
+    #[test]
+    fn data_only_check() {
+        for policy in [Policy::ReedSolomon(4), Policy::DataOnlyReedSolomon(4)].iter() {
+            let block = AllocBlock::new(16, &[*policy, Policy::Nil, Policy::Nil, Policy::Nil], false).unwrap();
+            let block_ref = block.get_ref_mut().unwrap();
+            for idx in 0..16 {
+                block_ref.data_slice()[idx] = idx as u8;
+            }
+            block_ref.apply_policy().unwrap();
+
+            // Errors in the parity alone are repaired by encoding again, errors in the data are
+            // decoded as usual, and both get the same result
+            block_ref.buffer()[17] ^= 0x11;
+            block_ref.buffer()[19] ^= 0x22;
+            assert_eq!(block_ref.correct_buffer(), Ok(2));
+            block_ref.buffer()[3] ^= 0x04;
+            block_ref.buffer()[18] ^= 0x08;
+            assert_eq!(block_ref.correct_buffer(), Ok(2));
+            assert_eq!(block_ref.is_corrupted(), false);
+            for idx in 0..16 {
+                assert_eq!(block_ref.data_slice()[idx], idx as u8);
+            }
+            block_ref.drop_ref();
+        }
+
+        let block = AllocBlock::new(8, &[Policy::DataOnlySecDed, Policy::Nil, Policy::Nil, Policy::Nil], false)
+            .unwrap();
+        let block_ref = block.get_ref_mut().unwrap();
+        for idx in 0..8 {
+            block_ref.data_slice()[idx] = idx as u8;
+        }
+        block_ref.apply_policy().unwrap();
+
+        // A flipped data bit is corrected like SEC-DED
+        block_ref.buffer()[6] ^= 0x10;
+        assert_eq!(block_ref.correct_buffer(), Ok(1));
+        // Three flips in the check byte point past the data, so plain SEC-DED cannot tell where
+        // they are, but here they can only be in the check byte
+        block_ref.buffer()[8] ^= 0xe0;
+        assert_eq!(block_ref.correct_buffer(), Ok(3));
+        assert_eq!(block_ref.is_corrupted(), false);
+        for idx in 0..8 {
+            assert_eq!(block_ref.data_slice()[idx], idx as u8);
+        }
+        // Two flips in the data are still detected
+        block_ref.buffer()[0] ^= 0x03;
+        assert_eq!(block_ref.correct_buffer(), Err(ErError::Uncorrectable));
+    }
+
+    #[test]
+    fn secded_redundancy_check() {
+        let block = AllocBlock::new(
+            16,
+            &[Policy::Redundancy(3), Policy::SecDed, Policy::Nil, Policy::Nil],
+            false,
+        ).unwrap();
+        let block_ref = block.get_ref_mut().unwrap();
+        for idx in 0..16 {
+            block_ref.data_slice()[idx] = idx as u8;
+        }
+        block_ref.apply_policy().unwrap();
+
+        // The copy SEC-DED cannot correct is outvoted by the others
+        block_ref.buffer()[2] ^= 0x03;
+        assert_eq!(block_ref.correct_buffer(), Ok(2));
+        assert_eq!(block_ref.is_corrupted(), false);
+        for idx in 0..16 {
+            assert_eq!(block_ref.data_slice()[idx], idx as u8);
+        }
+    }
+
+    #[test]
+    fn fec_erasure_hint_check() {
+        let block = AllocBlock::new(
+            16,
+            &[Policy::Redundancy(3), Policy::ReedSolomon(2), Policy::Nil, Policy::Nil],
+            false,
+        ).unwrap();
+        let block_ref = block.get_ref_mut().unwrap();
+        for idx in 0..16 {
+            block_ref.data_slice()[idx] = idx as u8;
+        }
+        block_ref.apply_policy().unwrap();
+
+        // Two copies hit at the same bits outvote the third, and each has more errors than
+        // Reed Solomon can find on its own. Where the copies disagree is enough to erase them.
+        let copy_len = block_ref.header.buffer_size / 3;
+        for copy in 0..2 {
+            block_ref.buffer()[copy * copy_len + 4] ^= 0x01;
+            block_ref.buffer()[copy * copy_len + 9] ^= 0x40;
+        }
+        assert_eq!(block_ref.correct_buffer(), Ok(4));
+        assert_eq!(block_ref.is_corrupted(), false);
+        for idx in 0..16 {
+            assert_eq!(block_ref.data_slice()[idx], idx as u8);
+        }
+    }
+
+    #[test]
+    fn linear_code_check() {
+        // 10 bytes are two whole 32 bit words and a short one, with a check byte each
+        let block = AllocBlock::new(
+            10,
+            &[Policy::LinearCode(codes::SEC_DAED_39_32_ID), Policy::Nil, Policy::Nil, Policy::Nil],
+            false,
+        ).unwrap();
+        let block_ref = block.get_ref_mut().unwrap();
+        assert_eq!(block_ref.header.buffer_size, 13);
+        for idx in 0..10 {
+            block_ref.data_slice()[idx] = (idx * 29) as u8;
+        }
+        block_ref.apply_policy().unwrap();
+
+        // Two adjacent flips, across a byte boundary, and one in the short word
+        block_ref.buffer()[4] ^= 0x80;
+        block_ref.buffer()[5] ^= 0x01;
+        block_ref.buffer()[9] ^= 0x04;
+        assert_eq!(block_ref.correct_buffer(), Ok(3));
+        assert_eq!(block_ref.is_corrupted(), false);
+        for idx in 0..10 {
+            assert_eq!(block_ref.data_slice()[idx], (idx * 29) as u8);
+        }
+
+        // The top bit of each check byte is not used by a code with 7 check bits
+        block_ref.buffer()[11] ^= 0x80;
+        assert_eq!(block_ref.is_corrupted(), false);
+        block_ref.drop_ref();
+
+        // A (12,8) Hamming code from its parity-check matrix
+        let columns = [0x3, 0x5, 0x6, 0x7, 0x9, 0xa, 0xb, 0xc, 0x1, 0x2, 0x4, 0x8];
+        let id = codes::register(LinearCode::new(&columns, 8).unwrap());
+        let block = AllocBlock::new(3, &[Policy::LinearCode(id), Policy::Nil, Policy::Nil, Policy::Nil], false)
+            .unwrap();
+        let block_ref = block.get_ref_mut().unwrap();
+        assert_eq!(block_ref.header.buffer_size, 6);
+        block_ref.data_slice().copy_from_slice(&[0xa5, 0x3c, 0xff]);
+        block_ref.apply_policy().unwrap();
+        for (byte, mask) in [(0, 0x20), (2, 0x01), (4, 0x08)].iter() {
+            block_ref.buffer()[*byte] ^= mask;
+            assert_eq!(block_ref.correct_buffer(), Ok(1));
+        }
+        assert_eq!(block_ref.data_slice(), &[0xa5, 0x3c, 0xff]);
+    }
+
+    #[test]
+    fn checksum_check() {
+        for width in [32, 64].iter() {
+            let block = AllocBlock::new(50, &[Policy::Checksum(*width), Policy::Nil, Policy::Nil, Policy::Nil], false)
+                .unwrap();
+            let block_ref = block.get_ref_mut().unwrap();
+            assert_eq!(block_ref.header.buffer_size, 50 + (*width / 8) as usize);
+            for idx in 0..50 {
+                block_ref.data_slice()[idx] = idx as u8;
+            }
+            block_ref.apply_policy().unwrap();
+            assert_eq!(block_ref.correct_buffer(), Ok(0));
+
+            // Errors are only detected
+            block_ref.buffer()[17] ^= 0x04;
+            assert_eq!(block_ref.is_corrupted(), true);
+            assert_eq!(block_ref.correct_buffer(), Err(ErError::Uncorrectable));
+        }
+    }
+
+    #[test]
+    fn checksum_redundancy_check() {
+        let block = AllocBlock::new(
+            20,
+            &[Policy::Redundancy(3), Policy::Checksum(64), Policy::Nil, Policy::Nil],
+            false,
+        ).unwrap();
+        let block_ref = block.get_ref_mut().unwrap();
+        for idx in 0..20 {
+            block_ref.data_slice()[idx] = idx as u8;
+        }
+        block_ref.apply_policy().unwrap();
+
+        // The vote fixes a single bad copy, and the checksum confirms it
+        block_ref.buffer()[5] ^= 0x01;
+        assert_eq!(block_ref.correct_buffer(), Ok(1));
+        assert_eq!(block_ref.is_corrupted(), false);
+
+        // Two copies flipped the same way win the vote, which the checksum catches
+        let copy_len = 20 + 8;
+        block_ref.buffer()[5] ^= 0x01;
+        block_ref.buffer()[copy_len + 5] ^= 0x01;
+        assert_eq!(block_ref.correct_buffer(), Err(ErError::Uncorrectable));
+    }
+
+    #[test]
+    fn checksum_reed_solomon_check() {
+        // The checksum covers the data and parity of Reed Solomon, and is checked once it is done
+        let block = AllocBlock::new(
+            20,
+            &[Policy::Checksum(32), Policy::ReedSolomon(4), Policy::Nil, Policy::Nil],
+            false,
+        ).unwrap();
+        let block_ref = block.get_ref_mut().unwrap();
+        assert_eq!(block_ref.header.buffer_size, 20 + 4 + 4);
+        for idx in 0..20 {
+            block_ref.data_slice()[idx] = idx as u8;
+        }
+        block_ref.apply_policy().unwrap();
+
+        // Errors that Reed Solomon can correct do not reach the checksum
+        block_ref.buffer()[3] ^= 0xff;
+        block_ref.buffer()[21] ^= 0x10;
+        assert_eq!(block_ref.correct_buffer(), Ok(2));
+        assert_eq!(block_ref.is_corrupted(), false);
+        for idx in 0..20 {
+            assert_eq!(block_ref.data_slice()[idx], idx as u8);
+        }
+
+        // Too many for Reed Solomon, which the checksum catches
+        for byte in 0..3 {
+            block_ref.buffer()[byte] ^= 0x01;
+        }
+        assert_eq!(block_ref.correct_buffer(), Err(ErError::Uncorrectable));
+    }
+
+    #[test]
+    fn unequal_protection_check() {
+        // The data length is recovered from the buffer length for any width and mask
+        for (width, mask) in [(8, 0x80), (16, 0xf000), (32, 0xffff_ff00), (64, !0), (16, 0)].iter() {
+            let (width_bytes, n_bits) = uep_params(*width, *mask);
+            for size in 0..200 {
+                let policies = [Policy::UnequalProtection(*width, *mask), Policy::Nil, Policy::Nil, Policy::Nil];
+                let buffer_size = AllocBlock::size_of(size, &policies);
+                assert_eq!(uep_data_len(buffer_size, width_bytes, n_bits), size);
+            }
+        }
+
+        // Five 16 bit values, of which only the top 4 bits matter, are 10 bytes
+        // and two copies of 20 bits
+        let block = AllocBlock::new(
+            10,
+            &[Policy::UnequalProtection(16, 0xf000), Policy::Nil, Policy::Nil, Policy::Nil],
+            false,
+        ).unwrap();
+        let block_ref = block.get_ref_mut().unwrap();
+        assert_eq!(block_ref.header.buffer_size, 10 + 2 * 3);
+        let values: [u16; 5] = [0x1234, 0xabcd, 0x8000, 0x7fff, 0xf00f];
+        for (idx, value) in values.iter().enumerate() {
+            block_ref.data_slice()[2 * idx..2 * idx + 2].copy_from_slice(&value.to_le_bytes());
+        }
+        block_ref.apply_policy().unwrap();
+
+        // A flip in a significant bit is voted out, in the data or in a copy
+        block_ref.buffer()[3] ^= 0x80;
+        block_ref.buffer()[14] ^= 0x02;
+        assert_eq!(block_ref.is_corrupted(), true);
+        assert_eq!(block_ref.correct_buffer(), Ok(2));
+        assert_eq!(block_ref.is_corrupted(), false);
+        for (idx, value) in values.iter().enumerate() {
+            assert_eq!(block_ref.data_slice()[2 * idx..2 * idx + 2], value.to_le_bytes());
+        }
+
+        // The other bits are left to the outer policies
+        block_ref.buffer()[4] ^= 0x01;
+        assert_eq!(block_ref.is_corrupted(), false);
+    }
+
+    #[test]
+    fn unequal_protection_secded_check() {
+        let block = AllocBlock::new(
+            16,
+            &[Policy::SecDed, Policy::UnequalProtection(32, 0x8000_0000), Policy::Nil, Policy::Nil],
+            false,
+        ).unwrap();
+        let block_ref = block.get_ref_mut().unwrap();
+        for idx in 0..16 {
+            block_ref.data_slice()[idx] = idx as u8;
+        }
+        block_ref.apply_policy().unwrap();
+
+        // Two flips in one word are too many for SEC-DED alone, but the sign bit is voted back first
+        block_ref.buffer()[3] ^= 0x80;
+        block_ref.buffer()[4] ^= 0x01;
+        assert_eq!(block_ref.correct_buffer(), Ok(2));
+        assert_eq!(block_ref.is_corrupted(), false);
+        for idx in 0..16 {
+            assert_eq!(block_ref.data_slice()[idx], idx as u8);
+        }
+    }
+
+    #[test]
+    fn encryption_nonce_check() {
+        let _lock = CRYPTO_TESTS.lock();
+        let block = AllocBlock::new(
+            32,
+            &[Policy::ReedSolomon(4), Policy::Encrypted, Policy::Nil, Policy::Nil],
+            true,
+        ).unwrap();
+
+        let block_ref = block.get_ref_mut().unwrap();
+        let mut first = [0u8; 32 + CRYPT_TRAILER_LEN];
+        first.copy_from_slice(&block_ref.buffer()[..32 + CRYPT_TRAILER_LEN]);
+        block_ref.decrypt_buffer().unwrap();
+        block_ref.apply_policy().unwrap();
+        let mut second = [0u8; 32 + CRYPT_TRAILER_LEN];
+        second.copy_from_slice(&block_ref.buffer()[..32 + CRYPT_TRAILER_LEN]);
+        // Every write uses a fresh nonce, so the same plaintext encrypts differently
+        assert_ne!(first[32..32 + NONCE_LEN], second[32..32 + NONCE_LEN]);
+        assert_ne!(first[..32], second[..32]);
+
+        block_ref.decrypt_buffer().unwrap();
+        assert_eq!(block_ref.data_slice(), &[0u8; 32][..]);
+        // Re-encrypting after a read reproduces the ciphertext, so the parity stays valid
+        block_ref.encrypt_buffer().unwrap();
+        assert_eq!(&block_ref.buffer()[..32 + CRYPT_TRAILER_LEN], &second[..]);
+        assert_eq!(block_ref.is_corrupted(), false);
+    }
+
+    #[test]
+    fn block_key_check() {
+        let _lock = CRYPTO_TESTS.lock();
+        let policies = [Policy::Encrypted, Policy::Nil, Policy::Nil, Policy::Nil];
+        let first = AllocBlock::new(32, &policies, true).unwrap();
+        let second = AllocBlock::new(32, &policies, true).unwrap();
+        let first_ref = first.get_ref_mut().unwrap();
+        let second_ref = second.get_ref_mut().unwrap();
+        assert_ne!(first_ref.header.id, second_ref.header.id);
+
+        // Even with the same nonce, key and plaintext, each block has its own keystream
+        second_ref.decrypt_buffer().unwrap();
+        let nonce_range = 32..32 + CRYPT_TRAILER_LEN;
+        second_ref.buffer()[nonce_range.clone()].copy_from_slice(&first_ref.buffer()[nonce_range]);
+        second_ref.encrypt_buffer().unwrap();
+        assert_ne!(first_ref.buffer()[..32], second_ref.buffer()[..32]);
+
+        second_ref.decrypt_buffer().unwrap();
+        assert_eq!(second_ref.data_slice(), &[0u8; 32][..]);
+        second_ref.encrypt_buffer().unwrap();
+    }
+
+    #[test]
+    fn read_check() {
+        let _lock = CRYPTO_TESTS.lock();
+        for crypt in [Policy::Encrypted, Policy::Authenticated].iter() {
+            let block = AllocBlock::new(300, &[Policy::ReedSolomon(4), *crypt, Policy::Nil, Policy::Nil], false)
+                .unwrap();
+            let block_ref = block.get_ref_mut().unwrap();
+            for idx in 0..300 {
+                block_ref.data_slice()[idx] = idx as u8;
+            }
+            block_ref.apply_policy().unwrap();
+            let stored = Vec::from(&block_ref.buffer()[..]);
+
+            // Reads past the first AES block seek the keystream
+            let mut dest = [0u8; 40];
+            block_ref.read(&mut dest, 37).unwrap();
+            for idx in 0..40 {
+                assert_eq!(dest[idx], (idx + 37) as u8);
+            }
+            assert_eq!(&block_ref.buffer()[..], &stored[..]);
+        }
+    }
+
+    #[test]
+    fn authenticated_check() {
+        let _lock = CRYPTO_TESTS.lock();
+        let block = AllocBlock::new(
+            32,
+            &[Policy::ReedSolomon(4), Policy::Authenticated, Policy::Nil, Policy::Nil],
+            true,
+        ).unwrap();
+
+        let block_ref = block.get_ref_mut().unwrap();
+        // Flips that error correction can handle do not affect authentication
+        block_ref.buffer()[5] ^= 0x21;
+        assert_eq!(block_ref.correct_buffer(), Ok(1));
+        assert_eq!(block_ref.decrypt_buffer(), Ok(()));
+        assert_eq!(block_ref.data_slice(), &[0u8; 32][..]);
+        block_ref.encrypt_buffer().unwrap();
+        assert_eq!(block_ref.is_corrupted(), false);
+
+        // Anything left over after correction is caught by the tag
+        block_ref.buffer()[5] ^= 0x21;
+        assert_eq!(block_ref.decrypt_buffer(), Err(ErError::TagMismatch));
+        block_ref.buffer()[5] ^= 0x21;
+        assert_eq!(block_ref.decrypt_buffer(), Ok(()));
+        assert_eq!(block_ref.data_slice(), &[0u8; 32][..]);
+        block_ref.encrypt_buffer().unwrap();
+    }
+
+    #[test]
+    fn unwritten_authenticated_check() {
+        let _lock = CRYPTO_TESTS.lock();
+        let block = AllocBlock::new(16, &[Policy::Authenticated, Policy::Nil, Policy::Nil, Policy::Nil], false).unwrap();
+
+        // The zeroed data was encrypted when the block was allocated, so it can be read back
+        let block_ref = block.get_ref_mut().unwrap();
+        assert_eq!(block_ref.decrypt_buffer(), Ok(()));
+        assert_eq!(block_ref.data_slice(), &[0u8; 16][..]);
+        block_ref.drop_ref();
+    }
+
+    #[test]
+    fn locked_wipe_check() {
+        let _lock = CRYPTO_TESTS.lock();
+        let policies = [Policy::Nil; MAX_POLICIES];
+        let block = AllocBlock::new_in(32, &policies, false, LockMode::Locked).unwrap().get_ref_mut().unwrap();
+        block.data_slice().copy_from_slice(&[0x42; 32]);
+        let data = block.ptr();
+        block.drop_ref();
+
+        // Locked memory is kept mapped once freed, so it must not keep the data
+        let freed = unsafe { core::slice::from_raw_parts(data, 32) };
+        assert!(freed.iter().all(|&byte| byte == FREED_POISON));
+    }
+
+    #[test]
+    fn key_rotation_check() {
+        let _lock = CRYPTO_TESTS.lock();
+        let block = AllocBlock::new(
+            300,
+            &[Policy::Redundancy(3), Policy::ReedSolomon(4), Policy::Authenticated, Policy::Nil],
+            false,
+        ).unwrap();
+
+        let block_ref = block.get_ref_mut().unwrap();
+        for idx in 0..300 {
+            block_ref.data_slice()[idx] = idx as u8;
+        }
+        block_ref.apply_policy().unwrap();
+        let (policy, region) = block_ref.crypt_buffer().unwrap();
+        let old_key_id = policy.key_id(region);
+
+        assert!(rotate_key().unwrap() >= 1);
+        let (policy, region) = block_ref.crypt_buffer().unwrap();
+        assert_ne!(policy.key_id(region), old_key_id);
+        assert_eq!(block_ref.is_corrupted(), false);
+        assert_eq!(block_ref.decrypt_buffer(), Ok(()));
+        for idx in 0..300 {
+            assert_eq!(block_ref.data_slice()[idx], idx as u8);
+        }
+        block_ref.encrypt_buffer().unwrap();
+    }
+
+    #[test]
+    fn interrupted_rotation_check() {
+        let _lock = CRYPTO_TESTS.lock();
+        let policies = [Policy::Authenticated, Policy::Nil, Policy::Nil, Policy::Nil];
+        let block = AllocBlock::new(16, &policies, false).unwrap().get_ref_mut().unwrap();
+        block.data_slice().copy_from_slice(&[7u8; 16]);
+        block.apply_policy().unwrap();
+        // Never written, but it was encrypted under the current key when it was allocated
+        let unwritten = AllocBlock::new(16, &[Policy::Encrypted, Policy::Nil, Policy::Nil, Policy::Nil], false)
+            .unwrap()
+            .get_ref_mut()
+            .unwrap();
+
+        // The block cannot be moved while it fails authentication, however often keys rotate
+        block.buffer()[3] ^= 0x40;
+        assert_eq!(rotate_key(), Err(ErError::TagMismatch));
+        assert_eq!(rotate_key(), Err(ErError::TagMismatch));
+        let (policy, region) = unwritten.crypt_buffer().unwrap();
+        assert_eq!(policy.key_id(region), crypto::current_key_id());
+
+        // Its key was kept, so it is readable again once the error is gone
+        block.buffer()[3] ^= 0x40;
+        assert_eq!(block.decrypt_buffer(), Ok(()));
+        assert_eq!(block.data_slice(), &[7u8; 16][..]);
+        block.encrypt_buffer().unwrap();
+        assert!(rotate_key().unwrap() >= 2);
+        block.drop_ref();
+        unwritten.drop_ref();
+    }
+
+    extern "C" fn rotate_repeatedly(_: *mut libc::c_void) -> *mut libc::c_void {
+        let failed = (0..20).any(|_| rotate_key().is_err());
+        failed as usize as *mut libc::c_void
+    }
+
+    #[test]
+    fn concurrent_rotation_check() {
+        let _lock = CRYPTO_TESTS.lock();
+        let policies = [Policy::Encrypted, Policy::Nil, Policy::Nil, Policy::Nil];
+        let blocks: Vec<_> = (0..16u8)
+            .map(|idx| {
+                let block = AllocBlock::new(64, &policies, false).unwrap().get_ref_mut().unwrap();
+                block.data_slice().copy_from_slice(&[idx; 64]);
+                block.apply_policy().unwrap();
+                block
+            })
+            .collect();
+
+        // Neither rotation may retire a key the other one just moved blocks to
+        let mut threads: [libc::pthread_t; 2] = [0; 2];
+        for thread in threads.iter_mut() {
+            let created = unsafe { libc::pthread_create(thread, ptr::null(), rotate_repeatedly, ptr::null_mut()) };
+            assert_eq!(created, 0);
+        }
+        for thread in threads.iter() {
+            let mut failed = ptr::null_mut();
+            unsafe { libc::pthread_join(*thread, &mut failed) };
+            assert!(failed.is_null());
+        }
+        for (idx, block) in blocks.into_iter().enumerate() {
+            assert_eq!(block.decrypt_buffer(), Ok(()));
+            assert_eq!(block.data_slice(), &[idx as u8; 64][..]);
+            block.drop_ref();
+        }
+    }
+
+    struct FailingKeyProvider;
+
+    impl KeyProvider for FailingKeyProvider {
+        fn key(&mut self, _key: &mut [u8; KEY_LEN]) -> Result<(), ErError> {
+            Err(ErError::KeyUnavailable)
+        }
+    }
+
+    #[test]
+    fn key_provider_check() {
+        let _lock = CRYPTO_TESTS.lock();
+        let block = AllocBlock::new(32, &[Policy::Encrypted, Policy::Nil, Policy::Nil, Policy::Nil], false).unwrap();
+
+        let block_ref = block.get_ref_mut().unwrap();
+        for idx in 0..32 {
+            block_ref.data_slice()[idx] = idx as u8;
+        }
+        block_ref.apply_policy().unwrap();
+
+        // The block stays under the old key when the new provider fails
+        assert_eq!(
+            set_key_provider(Box::new(FailingKeyProvider)),
+            Err(ErError::KeyUnavailable)
+        );
+        assert!(set_key_provider(Box::new(MemoryKeyProvider::new(&[7u8; KEY_LEN]))).unwrap() >= 1);
+        assert_eq!(block_ref.decrypt_buffer(), Ok(()));
+        for idx in 0..32 {
+            assert_eq!(block_ref.data_slice()[idx], idx as u8);
+        }
+        block_ref.encrypt_buffer().unwrap();
+    }
+
+    #[test]
+    fn locked_check() {
+        let _lock = CRYPTO_TESTS.lock();
+        let encrypted = AllocBlock::new(100, &[Policy::Encrypted, Policy::Nil, Policy::Nil, Policy::Nil], true)
+            .unwrap();
+        let plain = AllocBlock::new(100, &[Policy::Nil; MAX_POLICIES], false).unwrap();
+        // Large blocks get their own pages
+        let large = AllocBlock::new_in(10000, &[Policy::Nil; MAX_POLICIES], true, LockMode::Locked)
+            .unwrap();
+
+        let encrypted_ref = encrypted.get_ref_mut().unwrap();
+        let plain_ref = plain.get_ref_mut().unwrap();
+        let large_ref = large.get_ref_mut().unwrap();
+        assert_eq!(encrypted_ref.header.locked, true);
+        assert_eq!(plain_ref.header.locked, false);
+        assert_eq!(large_ref.header.locked, true);
+
+        encrypted_ref.decrypt_buffer().unwrap();
+        assert_eq!(encrypted_ref.data_slice(), &[0u8; 100][..]);
+        assert_eq!(large_ref.data_slice(), &[0u8; 10000][..]);
+        encrypted_ref.drop_ref();
+        plain_ref.drop_ref();
+        large_ref.drop_ref();
+    }
+
+    #[test]
+    fn renew_check() {
+        let block = AllocBlock::new(16, &[Policy::ReedSolomon(4), Policy::Nil, Policy::Nil, Policy::Nil], false).unwrap();
+        let block_ref = block.get_ref_mut().unwrap();
+        for idx in 0..16 {
+            block_ref.data_slice()[idx] = idx as u8;
+        }
+        block_ref.apply_policy().unwrap();
+        block_ref.buffer()[3] ^= 0x40;
+
+        // The error is corrected with the old policies before the data moves
+        let block = AllocBlock::renew(
+            WeakMut::from(block_ref),
+            32,
+            &[Policy::Redundancy(3), Policy::Nil, Policy::Nil, Policy::Nil],
+            LockMode::Default,
+        )
+        .unwrap();
+        let block_ref = block.get_ref_mut().unwrap();
+        assert_eq!(block_ref.header.buffer_size, 32 * 3);
+        assert_eq!(block_ref.is_corrupted(), false);
+        for idx in 0..16 {
+            assert_eq!(block_ref.data_slice()[idx], idx as u8);
+        }
+    }
+
+    struct Counts {
+        errors: [Option<u32>; 8],
+        n_codewords: usize,
+    }
+
+    impl CorrectionObserver for Counts {
+        fn codeword(&mut self, errors: Option<u32>) {
+            self.errors[self.n_codewords] = errors;
+            self.n_codewords += 1;
+        }
+    }
+
+    struct Disagreed(Vec<usize>);
+
+    impl CorrectionObserver for Disagreed {
+        fn copies_disagreed(&mut self, copies: &[usize]) {
+            self.0 = copies.to_vec();
+        }
+    }
+
+    #[test]
+    fn typed_redundancy_check() {
+        use crate::vote::{ElementType, VoteMode};
+
+        let readings = [21.5f32, -3.25];
+        let policy = |mode, epsilon| {
+            Policy::TypedRedundancy(4, TypedVote { element: ElementType::F32, mode, epsilon })
+        };
+        let setup = |policy: Policy| {
+            let block = AllocBlock::new(8, &[policy, Policy::Nil, Policy::Nil, Policy::Nil], false).unwrap();
+            let block_ref = block.get_ref_mut().unwrap();
+            block_ref.data_slice()[..4].copy_from_slice(&readings[0].to_le_bytes());
+            block_ref.data_slice()[4..].copy_from_slice(&readings[1].to_le_bytes());
+            block_ref.apply_policy().unwrap();
+            block_ref
+        };
+
+        // A different copy is hit in each element, and three copies still hold each of them
+        let block_ref = setup(policy(VoteMode::Majority, 0.0));
+        block_ref.buffer()[3] ^= 0x40;
+        block_ref.buffer()[24 + 4] ^= 0x01;
+        let mut disagreed = Disagreed(Vec::new());
+        assert_eq!(block_ref.correct_buffer_observed(&mut disagreed), Ok(2));
+        assert_eq!(disagreed.0, [0, 3]);
+        assert_eq!(block_ref.is_corrupted(), false);
+        assert_eq!(block_ref.data_slice()[..4], readings[0].to_le_bytes());
+
+        // Each copy of the first element is different, so there is no majority
+        for copy in 0..3 {
+            block_ref.buffer()[copy * 8 + 3] ^= 1 << copy;
+        }
+        assert_eq!(block_ref.correct_buffer(), Err(ErError::Uncorrectable));
+        block_ref.drop_ref();
+
+        // Three of the four copies are off, in both directions, and the median is the one that is not
+        let block_ref = setup(policy(VoteMode::Median, 0.0));
+        block_ref.buffer()[2] ^= 0x01;
+        block_ref.buffer()[8 + 3] ^= 0x80;
+        block_ref.buffer()[16] ^= 0x01;
+        assert_eq!(block_ref.correct_buffer(), Ok(3));
+        assert_eq!(block_ref.data_slice()[..4], readings[0].to_le_bytes());
+        block_ref.drop_ref();
+
+        // A flip in the last bit of the mantissa is within the tolerance, so the copy is rewritten
+        // but does not count as disagreeing
+        let block_ref = setup(policy(VoteMode::Tolerance, 1e-3));
+        block_ref.buffer()[0] ^= 0x01;
+        block_ref.buffer()[16 + 3] ^= 0x40;
+        let mut disagreed = Disagreed(Vec::new());
+        assert_eq!(block_ref.correct_buffer_observed(&mut disagreed), Ok(2));
+        assert_eq!(disagreed.0, [2]);
+        assert_eq!(block_ref.is_corrupted(), false);
+        assert_eq!(block_ref.data_slice()[..4], readings[0].to_le_bytes());
+    }
+
+    #[test]
+    fn checked_redundancy_check() {
+        let block = AllocBlock::new(4, &[Policy::CheckedRedundancy(2), Policy::Nil, Policy::Nil, Policy::Nil], false)
+            .unwrap();
+        let block_ref = block.get_ref_mut().unwrap();
+        assert_eq!(block_ref.header.buffer_size, 2 * (4 + 4));
+        block_ref.data_slice().copy_from_slice(&[0xf0, 0x0f, 0xaa, 0x55]);
+        block_ref.apply_policy().unwrap();
+
+        // A tie between two copies goes to the one that matches its checksum, whichever it is
+        block_ref.buffer()[1] ^= 0x01;
+        assert_eq!(block_ref.is_corrupted(), true);
+        assert_eq!(block_ref.correct_buffer(), Ok(1));
+        block_ref.buffer()[8 + 2] ^= 0x80;
+        block_ref.buffer()[8 + 5] ^= 0x01;
+        assert_eq!(block_ref.correct_buffer(), Ok(2));
+        assert_eq!(block_ref.is_corrupted(), false);
+        assert_eq!(block_ref.data_slice(), &[0xf0, 0x0f, 0xaa, 0x55]);
+
+        // With both copies hit, the vote cannot be checked out
+        block_ref.buffer()[0] ^= 0x01;
+        block_ref.buffer()[8 + 3] ^= 0x01;
+        assert_eq!(block_ref.correct_buffer(), Err(ErError::Uncorrectable));
+        block_ref.drop_ref();
+
+        // Copies that the inner code repairs are checked after it
+        let block = AllocBlock::new(
+            4,
+            &[Policy::CheckedRedundancy(2), Policy::ReedSolomon(2), Policy::Nil, Policy::Nil],
+            false,
+        ).unwrap();
+        let block_ref = block.get_ref_mut().unwrap();
+        assert_eq!(block_ref.header.buffer_size, 2 * (4 + 2 + 4));
+        block_ref.data_slice().copy_from_slice(&[1, 2, 3, 4]);
+        block_ref.apply_policy().unwrap();
+        // One symbol in the first copy is decoded, two in the second are too many for RS(2), so
+        // its 16 flipped bits are rewritten from the first
+        block_ref.buffer()[0] ^= 0xff;
+        block_ref.buffer()[10] ^= 0xff;
+        block_ref.buffer()[11] ^= 0xff;
+        assert_eq!(block_ref.correct_buffer(), Ok(1 + 16));
+        assert_eq!(block_ref.is_corrupted(), false);
+        assert_eq!(block_ref.data_slice(), &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn separated_redundancy_check() {
+        let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize };
+        let separated_copy = |block: &AllocBlock, copy| unsafe {
+            core::slice::from_raw_parts_mut(block.separated_copy(copy), block.header.buffer_size)
+        };
+        let block = AllocBlock::new(
+            16,
+            &[Policy::SeparatedRedundancy(3, 1), Policy::SecDed, Policy::Nil, Policy::Nil],
+            false,
+        ).unwrap();
+        let block_ref = block.get_ref_mut().unwrap();
+        // Only the first copy is stored after the header
+        assert_eq!(block_ref.header.buffer_size, 16 + 2);
+        for idx in 0..16 {
+            block_ref.data_slice()[idx] = idx as u8;
+        }
+        block_ref.apply_policy().unwrap();
+
+        // Every separated copy starts a page of its own, away from the block and each other
+        let pages = [block_ref.ptr(), block_ref.header.copies[0], block_ref.header.copies[1]]
+            .iter()
+            .map(|&ptr| ptr as usize / page)
+            .collect::<Vec<_>>();
+        assert!(pages[0] != pages[1] && pages[0] != pages[2] && pages[1] != pages[2]);
+        assert_eq!(block_ref.header.copies[0] as usize % page, 0);
+        assert_eq!(separated_copy(block_ref, 1), block_ref.buffer());
+
+        // A copy that is lost entirely is outvoted by the other two
+        for byte in separated_copy(block_ref, 0).iter_mut() {
+            *byte = !*byte;
+        }
+        assert_eq!(block_ref.is_corrupted(), true);
+        assert_eq!(block_ref.correct_buffer(), Ok(18 * 8));
+        assert_eq!(block_ref.is_corrupted(), false);
+        assert_eq!(separated_copy(block_ref, 0), block_ref.buffer());
+
+        // The inner code still corrects each copy before the vote
+        block_ref.buffer()[3] ^= 0x10;
+        separated_copy(block_ref, 1)[5] ^= 0x01;
+        assert_eq!(block_ref.correct_buffer(), Ok(2));
+        for idx in 0..16 {
+            assert_eq!(block_ref.data_slice()[idx], idx as u8);
+        }
+        block_ref.drop_ref();
+
+        // Without guard pages the copies come from the heap
+        let block = AllocBlock::new(4, &[Policy::SeparatedRedundancy(2, 0), Policy::Nil, Policy::Nil, Policy::Nil], true)
+            .unwrap();
+        let block_ref = block.get_ref_mut().unwrap();
+        assert_eq!(separated_copy(block_ref, 0), &[0, 0, 0, 0]);
+        separated_copy(block_ref, 0)[2] ^= 0x04;
+        assert_eq!(block_ref.is_corrupted(), true);
+        block_ref.drop_ref();
+    }
+
+    #[test]
+    fn header_check() {
+        let _lock = CRYPTO_TESTS.lock();
+        let block = AllocBlock::new(8, &[Policy::Redundancy(3), Policy::SecDed, Policy::Nil, Policy::Nil], false)
+            .unwrap();
+        let block_ref = block.get_ref_mut().unwrap();
+        block_ref.data_slice().copy_from_slice(&[1, 2, 3, 4, 5, 6, 7, 8]);
+        block_ref.apply_policy().unwrap();
+        let ptr = block_ref.ptr();
+        let header_ptr = &mut block_ref.header as *mut BlockHeader as *mut u8;
+        let backup_ptr = &mut block_ref.backup as *mut BlockHeader as *mut u8;
+
+        // A flip in the discriminant of the first policy, in the buffer size, and in the
+        // borrow flag are all repaired before the block is used
+        unsafe { *header_ptr ^= 0x04 };
+        block_ref.header.buffer_size ^= 1 << 40;
+        block_ref.weak_exists ^= 0x100;
+        let block = AllocBlock::from_usr_ptr_mut(ptr).unwrap();
+        let block_ref = block.get_ref_mut().unwrap();
+        assert_eq!(block_ref.header.buffer_size, (8 + 1) * 3);
+        assert_eq!(block_ref.correct_buffer(), Ok(0));
+        assert_eq!(block_ref.data_slice(), &[1, 2, 3, 4, 5, 6, 7, 8]);
+
+        // The backup is repaired from the header too
+        unsafe { *backup_ptr.add(9) ^= 0x20 };
+        assert_eq!(block_ref.verify_header(), Ok(true));
+        assert_eq!(block_ref.verify_header(), Ok(false));
+
+        // Bytes past the fields of Redundancy are not part of the header
+        unsafe { *header_ptr.add(16) ^= 0x80 };
+        assert_eq!(block_ref.verify_header(), Ok(false));
+        unsafe { *header_ptr.add(16) ^= 0x80 };
+
+        // A discriminant that no policy has is caught from the raw bytes
+        unsafe { *header_ptr.add(7) ^= 0x80 };
+        assert_eq!(block_ref.verify_header(), Ok(true));
+        assert_eq!(block_ref.correct_buffer(), Ok(0));
+
+        // With both hit, nothing can be trusted
+        unsafe {
+            *header_ptr.add(8) ^= 0x01;
+            *backup_ptr.add(8) ^= 0x01;
+        }
+        assert_eq!(AllocBlock::from_usr_ptr(ptr).err(), Some(ErError::CorruptHeader));
+        unsafe {
+            *header_ptr.add(8) ^= 0x01;
+            *backup_ptr.add(8) ^= 0x01;
+        }
+        AllocBlock::drop(AllocBlock::from_usr_ptr_mut(ptr).unwrap());
+
+        // So is an element type that no vote has, in the high byte of the enum
+        let vote = TypedVote { element: ElementType::U32, mode: VoteMode::Median, epsilon: 0.0 };
+        let block = AllocBlock::new(8, &[Policy::TypedRedundancy(3, vote), Policy::Nil, Policy::Nil, Policy::Nil], false)
+            .unwrap();
+        let block_ref = block.get_ref_mut().unwrap();
+        let header_ptr = &mut block_ref.header as *mut BlockHeader as *mut u8;
+        let element = core::mem::size_of::<RawPolicy<u32, ()>>() + 3;
+        unsafe { *header_ptr.add(element) ^= 0x80 };
+        assert_eq!(block_ref.verify_header(), Ok(true));
+        assert!(matches!(block_ref.header.policies[0], Policy::TypedRedundancy(3, v) if v == vote));
+        block_ref.drop_ref();
+    }
+
+    #[test]
+    fn usr_ptr_check() {
+        let _lock = CRYPTO_TESTS.lock();
+        let block = AllocBlock::new(32, &[Policy::Nil, Policy::Nil, Policy::Nil, Policy::Nil], false).unwrap();
+        let block_ref = block.get_ref_mut().unwrap();
+        let ptr = block_ref.ptr();
+
+        // Only the start of the data of a live block is accepted
+        assert_eq!(AllocBlock::from_usr_ptr(core::ptr::null()).err(), Some(ErError::InvalidPointer));
+        assert_eq!(AllocBlock::from_usr_ptr(unsafe { ptr.add(4) }).err(), Some(ErError::InvalidPointer));
+        assert_eq!(AllocBlock::from_usr_ptr(unsafe { ptr.add(16) }).err(), Some(ErError::InvalidPointer));
+
+        // A few flipped bits in the state are repaired
+        block_ref.state ^= 0x8001;
+        assert!(AllocBlock::from_usr_ptr(ptr).is_ok());
+        assert_eq!(block_ref.state, BLOCK_LIVE);
+
+        // A freed block is told apart from a foreign pointer, even with a flipped bit
+        block_ref.state = BLOCK_FREED ^ 0x10;
+        assert_eq!(AllocBlock::from_usr_ptr_mut(ptr).err(), Some(ErError::AlreadyFreed));
+        block_ref.state = BLOCK_LIVE;
+        AllocBlock::drop(AllocBlock::from_usr_ptr_mut(ptr).unwrap());
+    }
+
+    #[test]
+    fn for_each_block_check() {
+        let _lock = CRYPTO_TESTS.lock();
+        let block = AllocBlock::new(24, &[Policy::Redundancy(3), Policy::Nil, Policy::Nil, Policy::Nil], false)
+            .unwrap();
+        let block_ref = block.get_ref_mut().unwrap();
+        let (ptr, id) = (block_ref.ptr(), block_ref.header.id);
+
+        let mut found = None;
+        for_each_block(|info| {
+            if info.ptr == ptr {
+                found = Some((info.length, info.buffer_size, info.id));
+            }
+            true
+        })
+        .unwrap();
+        assert_eq!(found, Some((24, 24 * 3, id)));
+
+        // Iteration stops as soon as the callback asks it to
+        assert_eq!(for_each_block(|_| false), Ok(1));
+
+        // The registry is released before the callback is called, so it may free blocks
+        let mut freed = false;
+        for_each_block(|info| {
+            if info.ptr == ptr {
+                block_ref.drop_ref();
+                freed = true;
+            }
+            !freed
+        })
+        .unwrap();
+        assert!(freed);
+    }
+
+    #[test]
+    fn scrub_check() {
+        let _lock = CRYPTO_TESTS.lock();
+        let policies = [Policy::Redundancy(3), Policy::Nil, Policy::Nil, Policy::Nil];
+        // Blocks are only locked until the budget is used up, so with a budget of 1 byte the
+        // scrubber stops at the first block it can lock and never reaches blocks of other tests
+        let older = AllocBlock::new(4, &policies, false).unwrap().get_ref_mut().unwrap();
+        let block = AllocBlock::new(4, &policies, false).unwrap();
+        let block_ref = block.get_ref_mut().unwrap();
+        block_ref.data_slice().copy_from_slice(&[1, 2, 3, 4]);
+        block_ref.apply_policy().unwrap();
+        let id = block_ref.header.id;
+        block_ref.buffer()[5] ^= 0x10;
+        block_ref.buffer()[10] ^= 0x01;
+
+        // The block is scrubbed even though it is over budget, and the rest are left for later
+        let (stats, resume) = scrub_blocks(Some(id + 1), 1);
+        assert_eq!((stats.blocks, stats.bytes, stats.corrected, stats.uncorrectable), (1, 12, 2, 0));
+        assert_eq!(resume, Some(id));
+        assert_eq!(block_ref.buffer(), &[1, 2, 3, 4, 1, 2, 3, 4, 1, 2, 3, 4]);
+
+        // Held blocks are skipped, and the older block is scrubbed instead
+        assert!(block_ref.try_acquire());
+        assert!(!block_ref.try_acquire());
+        block_ref.buffer()[0] ^= 0x80;
+        let (stats, resume) = scrub_blocks(Some(id + 1), 1);
+        assert_eq!((stats.busy, stats.blocks, block_ref.buffer()[0]), (1, 1, 0x81));
+        assert!(resume.map_or(true, |below| below == older.header.id));
+        block_ref.release();
+        block_ref.drop_ref();
+        older.drop_ref();
+    }
+
+    #[test]
+    fn reed_solomon_field_check() {
+        // With the default field and generator the buffer is the same as for ReedSolomon
+        let mut buffers = Vec::new();
+        for policy in [Policy::ReedSolomon(4), Policy::ReedSolomonField(4, rs::DEFAULT_PARAMS)].iter() {
+            let block = AllocBlock::new(16, &[*policy, Policy::Nil, Policy::Nil, Policy::Nil], false).unwrap();
+            let block_ref = block.get_ref_mut().unwrap();
+            for idx in 0..16 {
+                block_ref.data_slice()[idx] = (idx * 13) as u8;
+            }
+            block_ref.apply_policy().unwrap();
+            buffers.push(block_ref.buffer().to_vec());
+            block_ref.drop_ref();
+        }
+        assert_eq!(buffers[0], buffers[1]);
+
+        // GF(2^4) codewords with 4 parity symbols carry 5 data bytes in 2 parity bytes
+        let nibbles = RsParams { symbol_bits: 4, polynomial: 0x13, first_root: 1 };
+        let block = AllocBlock::new(
+            12,
+            &[Policy::ReedSolomonField(4, nibbles), Policy::Nil, Policy::Nil, Policy::Nil],
+            false,
+        ).unwrap();
+        let block_ref = block.get_ref_mut().unwrap();
+        assert_eq!(block_ref.header.buffer_size, 12 + 3 * 2);
+        for idx in 0..12 {
+            block_ref.data_slice()[idx] = idx as u8 * 0x11;
+        }
+        block_ref.apply_policy().unwrap();
+
+        // Both symbols of a byte in the first codeword, one symbol in the second
+        // and one in the parity of the third
+        block_ref.buffer()[0] ^= 0xff;
+        block_ref.buffer()[7] ^= 0x01;
+        block_ref.buffer()[12 + 5] ^= 0x10;
+        assert_eq!(block_ref.correct_buffer(), Ok(3));
+        assert_eq!(block_ref.is_corrupted(), false);
+        for idx in 0..12 {
+            assert_eq!(block_ref.data_slice()[idx], idx as u8 * 0x11);
+        }
+
+        // Three symbols are too many for one codeword, which is left as it is
+        block_ref.buffer()[0] ^= 0xff;
+        block_ref.buffer()[1] ^= 0x01;
+        assert_eq!(block_ref.correct_buffer(), Ok(0));
+        assert_eq!(block_ref.is_corrupted(), true);
+    }
+
+    #[test]
+    fn fec_chunked_check() {
+        // 1000 bytes with 4 parity bytes per codeword needs 4 codewords of at most 251 data bytes
+        let block = AllocBlock::new(
+            1000,
+            &[Policy::ReedSolomon(4), Policy::Nil, Policy::Nil, Policy::Nil],
+            false,
+        ).unwrap();
+
+        let block_ref = block.get_ref_mut().unwrap();
+        assert_eq!(block_ref.header.buffer_size, 1000 + 4 * 4);
+        let slice = block_ref.buffer();
+        for idx in 0..1000 {
+            slice[idx] = idx as u8;
+        }
+        block_ref.apply_policy().unwrap();
+
+        // Two errors in the first codeword, one in the last data byte and
+        // one in the parity of the third codeword
+        let slice = block_ref.buffer();
+        slice[0] ^= 0xff;
+        slice[250] ^= 0x01;
+        slice[999] ^= 0x80;
+        slice[1000 + 2 * 4] ^= 0x10;
+        assert_eq!(block_ref.is_corrupted(), true);
+
+        let mut counts = Counts { errors: [None; 8], n_codewords: 0 };
+        assert_eq!(block_ref.correct_buffer_observed(&mut counts), Ok(4));
+        assert_eq!(counts.n_codewords, 4);
+        assert_eq!(&counts.errors[..4], &[Some(2), Some(0), Some(1), Some(1)]);
+        assert_eq!(block_ref.is_corrupted(), false);
+        let slice = block_ref.buffer();
+        for idx in 0..1000 {
+            assert_eq!(slice[idx], idx as u8);
+        }
+    }
 }
@@ -0,0 +1,278 @@
+extern crate core;
+
+use core::ptr;
+
+use crate::crc::crc32;
+use crate::error::ErError;
+
+/// Links embedded in every registered item, forming an intrusive doubly linked list.
+/// Embedding them means registering never allocates.
+///
+/// The links carry a checksum, so a flipped bit is caught before a link is followed. The
+/// checksum is offset so that empty links check as zero, which lets an empty registry be built
+/// in a const.
+pub struct Links<T> {
+    prev: *mut T,
+    next: *mut T,
+    check: u32,
+}
+
+impl<T> Default for Links<T> {
+    fn default() -> Self {
+        Links::new(ptr::null_mut(), ptr::null_mut())
+    }
+}
+
+impl<T> Links<T> {
+    const fn empty() -> Self {
+        Links { prev: ptr::null_mut(), next: ptr::null_mut(), check: 0 }
+    }
+
+    fn new(prev: *mut T, next: *mut T) -> Self {
+        Links { prev, next, check: Links::checksum(prev, next) }
+    }
+
+    fn checksum(prev: *mut T, next: *mut T) -> u32 {
+        let mut bytes = [0u8; 16];
+        bytes[..8].copy_from_slice(&(prev as usize as u64).to_le_bytes());
+        bytes[8..].copy_from_slice(&(next as usize as u64).to_le_bytes());
+        crc32(&bytes) ^ crc32(&[0u8; 16])
+    }
+
+    fn is_intact(&self) -> bool {
+        self.check == Links::checksum(self.prev, self.next)
+    }
+}
+
+pub trait Registrable: Sized {
+    fn links(&self) -> &Links<Self>;
+    fn links_mut(&mut self) -> &mut Links<Self>;
+}
+
+/// The list of all live items. It should be kept behind a lock.
+///
+/// Both ends of the list are kept (`prev` is the tail and `next` the head), along with a
+/// backup of them. A node whose links are broken is repaired from its neighbours: the node
+/// before it is known from walking forward, and the node after it is the one whose `prev`
+/// points back at it, found by walking backward from the tail. Only if a second link is broken
+/// on the way can the nodes between the two not be reached, and the list is then cut short
+/// rather than followed through a bad pointer.
+pub struct Registry<T> where T: Registrable {
+    ends: Links<T>,
+    backup: Links<T>,
+}
+
+// The registry only hands out its pointers while it is locked
+unsafe impl<T> Send for Registry<T> where T: Registrable {}
+
+impl<T> Registry<T> where T: Registrable {
+    pub const fn new() -> Self {
+        Registry { ends: Links::empty(), backup: Links::empty() }
+    }
+
+    /// Adds an item to the front of the list. The item must not move until it is removed.
+    pub unsafe fn insert(&mut self, item: *mut T) {
+        self.check_ends();
+        let head = self.ends.next;
+        if !head.is_null() && !self.is_intact(head) {
+            let _ = self.repair();
+        }
+        let (tail, head) = (self.ends.prev, self.ends.next);
+
+        *(*item).links_mut() = Links::new(ptr::null_mut(), head);
+        match head.is_null() {
+            true => self.set_ends(item, item),
+            false => {
+                let next = (*head).links().next;
+                *(*head).links_mut() = Links::new(item, next);
+                self.set_ends(tail, item);
+            }
+        }
+    }
+
+    /// Removes an item that was previously inserted.
+    ///
+    /// Neighbours are only relinked if they still point at the item, so an item that was cut
+    /// off by `repair` is simply forgotten.
+    pub unsafe fn remove(&mut self, item: *mut T) {
+        self.check_ends();
+        if !self.is_intact(item) {
+            let _ = self.repair();
+        }
+        let links = (*item).links();
+        if !links.is_intact() {
+            *(*item).links_mut() = Links::default();
+            return;
+        }
+        let (prev, next) = (links.prev, links.next);
+        for &neighbour in [prev, next].iter() {
+            if !neighbour.is_null() && !self.is_intact(neighbour) {
+                let _ = self.repair();
+            }
+        }
+
+        let (mut head, mut tail) = (self.ends.next, self.ends.prev);
+        if prev.is_null() {
+            if head == item {
+                head = next;
+            }
+        } else if self.is_intact(prev) && (*prev).links().next == item {
+            let before = (*prev).links().prev;
+            *(*prev).links_mut() = Links::new(before, next);
+        }
+        if next.is_null() {
+            if tail == item {
+                tail = prev;
+            }
+        } else if self.is_intact(next) && (*next).links().prev == item {
+            let after = (*next).links().next;
+            *(*next).links_mut() = Links::new(prev, after);
+        }
+        self.set_ends(tail, head);
+        *(*item).links_mut() = Links::default();
+    }
+
+    /// Calls `f` on every item in the list, most recently inserted first. Every link is checked
+    /// before it is followed, and repaired if it is broken.
+    ///
+    /// Returns the number of nodes that were repaired. Fails with `ErError::CorruptRegistry` if
+    /// some nodes could not be reached, in which case `f` is still called on the others.
+    pub fn for_each<F>(&mut self, mut f: F) -> Result<u32, ErError> where F: FnMut(*mut T) {
+        unsafe { self.check_ends() };
+        let mut repaired = 0;
+        let mut lost = false;
+        let mut prev: *mut T = ptr::null_mut();
+        let mut cur = self.ends.next;
+        while !cur.is_null() {
+            let intact = unsafe { self.is_intact(cur) && (*cur).links().prev == prev };
+            if !intact {
+                // The node before is known, and the one after points back at this one
+                let next = unsafe { self.find_next(cur) };
+                unsafe { *(*cur).links_mut() = Links::new(prev, next.unwrap_or(ptr::null_mut())) };
+                match next {
+                    Some(_) => repaired += 1,
+                    // Cut the list here, so it is whole again
+                    None => {
+                        lost = true;
+                        self.set_ends(cur, self.ends.next);
+                    }
+                }
+            }
+
+            // Read the link first in case `f` modifies the item
+            let next = unsafe { (*cur).links().next };
+            f(cur);
+            prev = cur;
+            cur = next;
+        }
+
+        match lost {
+            true => Err(ErError::CorruptRegistry),
+            false => Ok(repaired),
+        }
+    }
+
+    /// Checks every link and repairs the broken ones, see `for_each`.
+    pub fn repair(&mut self) -> Result<u32, ErError> {
+        self.for_each(|_| ())
+    }
+
+    /// Finds the node after `node` by walking backward from the tail. Returns `Some(null)` if
+    /// `node` is the tail, and `None` if a broken link was found on the way.
+    unsafe fn find_next(&self, node: *mut T) -> Option<*mut T> {
+        let mut after: *mut T = ptr::null_mut();
+        let mut cur = self.ends.prev;
+        while !cur.is_null() {
+            if cur == node {
+                return Some(after);
+            }
+            if !self.is_intact(cur) {
+                return None;
+            }
+            after = cur;
+            cur = (*cur).links().prev;
+        }
+        None
+    }
+
+    unsafe fn is_intact(&self, node: *mut T) -> bool {
+        (*node).links().is_intact()
+    }
+
+    /// Restores the ends of the list from the backup if they were corrupted, or the other way
+    /// around. If both were, the registry starts over empty, since no node can be trusted to
+    /// lead to the others.
+    unsafe fn check_ends(&mut self) {
+        match (self.ends.is_intact(), self.backup.is_intact()) {
+            (true, true) => (),
+            (true, false) => self.backup = Links::new(self.ends.prev, self.ends.next),
+            (false, true) => self.ends = Links::new(self.backup.prev, self.backup.next),
+            (false, false) => self.set_ends(ptr::null_mut(), ptr::null_mut()),
+        }
+    }
+
+    fn set_ends(&mut self, tail: *mut T, head: *mut T) {
+        self.ends = Links::new(tail, head);
+        self.backup = Links::new(tail, head);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+
+    use super::*;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    struct Node {
+        links: Links<Node>,
+    }
+
+    impl Registrable for Node {
+        fn links(&self) -> &Links<Node> {
+            &self.links
+        }
+
+        fn links_mut(&mut self) -> &mut Links<Node> {
+            &mut self.links
+        }
+    }
+
+    fn visit(registry: &mut Registry<Node>, nodes: &[Node]) -> (Vec<usize>, Result<u32, ErError>) {
+        let mut order = Vec::new();
+        let result = registry.for_each(|node| {
+            order.push(nodes.iter().position(|n| n as *const Node == node as *const Node).unwrap())
+        });
+        (order, result)
+    }
+
+    #[test]
+    fn registry_check() {
+        let mut nodes: Vec<Node> = (0..5).map(|_| Node { links: Links::default() }).collect();
+        let mut registry = Registry::new();
+        for node in nodes.iter_mut() {
+            unsafe { registry.insert(node) };
+        }
+        unsafe { registry.remove(&mut nodes[2]) };
+        assert_eq!(visit(&mut registry, &nodes), (vec![4, 3, 1, 0], Ok(0)));
+
+        // A flipped bit in a link, and in the ends, is repaired on the way
+        nodes[3].links.next = (nodes[3].links.next as usize ^ 0x100) as *mut Node;
+        registry.ends.prev = (registry.ends.prev as usize ^ 0x8) as *mut Node;
+        assert_eq!(visit(&mut registry, &nodes), (vec![4, 3, 1, 0], Ok(1)));
+        assert_eq!(visit(&mut registry, &nodes), (vec![4, 3, 1, 0], Ok(0)));
+        unsafe { registry.remove(&mut nodes[0]) };
+        unsafe { registry.insert(&mut nodes[2]) };
+        assert_eq!(visit(&mut registry, &nodes), (vec![2, 4, 3, 1], Ok(0)));
+
+        // With two nodes broken, the ones after the first cannot be reached
+        nodes[4].links.next = ptr::null_mut();
+        nodes[1].links.prev = ptr::null_mut();
+        assert_eq!(visit(&mut registry, &nodes), (vec![2, 4], Err(ErError::CorruptRegistry)));
+        assert_eq!(visit(&mut registry, &nodes), (vec![2, 4], Ok(0)));
+        unsafe { registry.remove(&mut nodes[3]) };
+        unsafe { registry.remove(&mut nodes[4]) };
+        assert_eq!(visit(&mut registry, &nodes), (vec![2], Ok(0)));
+    }
+}
@@ -0,0 +1,507 @@
+extern crate alloc;
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::sync::SpinLock;
+use crate::zeroize;
+
+/// The field and generator of a Reed-Solomon code.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RsParams {
+    /// The number of bits in a symbol: 8, or 4 for small allocations. GF(2^4) codewords are at
+    /// most 15 symbols long, and every byte holds two symbols, low nibble first.
+    pub symbol_bits: u32,
+    /// The primitive polynomial of the field, including its x^symbol_bits term.
+    pub polynomial: u32,
+    /// The power of the primitive element that is the first consecutive root of the generator
+    /// polynomial.
+    pub first_root: u32,
+}
+
+/// The parameters of the `reed_solomon` crate, which is what `Policy::ReedSolomon` has always used.
+pub const DEFAULT_PARAMS: RsParams = RsParams { symbol_bits: 8, polynomial: 0x11d, first_root: 0 };
+
+impl RsParams {
+    /// The maximum length of a codeword in symbols.
+    fn codeword_len(&self) -> usize {
+        (1 << self.symbol_bits) - 1
+    }
+
+    fn symbols_per_byte(&self) -> usize {
+        8 / self.symbol_bits as usize
+    }
+
+    /// The number of data bytes carried by each codeword with `n_ecc` parity symbols.
+    pub fn chunk_len(&self, n_ecc: usize) -> usize {
+        (self.codeword_len() - n_ecc) / self.symbols_per_byte()
+    }
+
+    /// The number of bytes the `n_ecc` parity symbols of each codeword are stored in.
+    pub fn ecc_len(&self, n_ecc: usize) -> usize {
+        (n_ecc + self.symbols_per_byte() - 1) / self.symbols_per_byte()
+    }
+
+    /// Whether `n_ecc` parity symbols leave room for data in a codeword.
+    pub fn fits(&self, n_ecc: usize) -> bool {
+        (self.symbol_bits == 4 || self.symbol_bits == 8)
+            && n_ecc > 0
+            && n_ecc < self.codeword_len()
+            && self.chunk_len(n_ecc) > 0
+    }
+}
+
+/// GF(2^m) with exponent and logarithm tables for the primitive element x.
+struct Field {
+    // The number of nonzero elements, 2^m - 1
+    order: usize,
+    exp: [u8; 256],
+    log: [u8; 256],
+}
+
+impl Field {
+    /// The field for `polynomial`, or `None` if it is not primitive.
+    fn new(symbol_bits: u32, polynomial: u32) -> Option<Self> {
+        if (symbol_bits != 4 && symbol_bits != 8) || polynomial >> symbol_bits != 1 {
+            return None;
+        }
+        let order = (1 << symbol_bits) - 1;
+        let mut field = Field { order, exp: [0; 256], log: [0; 256] };
+        let mut element = 1u32;
+        for power in 0..order {
+            // x only generates every nonzero element if the polynomial is primitive
+            if power > 0 && element == 1 {
+                return None;
+            }
+            field.exp[power] = element as u8;
+            field.log[element as usize] = power as u8;
+            element <<= 1;
+            if element >> symbol_bits != 0 {
+                element ^= polynomial;
+            }
+        }
+        match element {
+            1 => Some(field),
+            _ => None,
+        }
+    }
+
+    fn alpha_pow(&self, power: isize) -> u8 {
+        self.exp[power.rem_euclid(self.order as isize) as usize]
+    }
+
+    fn mul(&self, a: u8, b: u8) -> u8 {
+        match (a, b) {
+            (0, _) | (_, 0) => 0,
+            _ => self.alpha_pow(self.log[a as usize] as isize + self.log[b as usize] as isize),
+        }
+    }
+
+    fn div(&self, a: u8, b: u8) -> u8 {
+        match a {
+            0 => 0,
+            _ => self.alpha_pow(self.log[a as usize] as isize - self.log[b as usize] as isize),
+        }
+    }
+
+    fn pow(&self, a: u8, power: isize) -> u8 {
+        match a {
+            0 => 0,
+            _ => self.alpha_pow(self.log[a as usize] as isize * power),
+        }
+    }
+
+    fn inv(&self, a: u8) -> u8 {
+        self.div(1, a)
+    }
+
+    // Polynomials are stored highest degree first
+
+    fn poly_scale(&self, p: &[u8], x: u8) -> Vec<u8> {
+        p.iter().map(|&c| self.mul(c, x)).collect()
+    }
+
+    fn poly_add(&self, p: &[u8], q: &[u8]) -> Vec<u8> {
+        let len = p.len().max(q.len());
+        let mut sum = vec![0u8; len];
+        for (idx, &c) in p.iter().enumerate() {
+            sum[idx + len - p.len()] ^= c;
+        }
+        for (idx, &c) in q.iter().enumerate() {
+            sum[idx + len - q.len()] ^= c;
+        }
+        sum
+    }
+
+    fn poly_mul(&self, p: &[u8], q: &[u8]) -> Vec<u8> {
+        let mut product = vec![0u8; p.len() + q.len() - 1];
+        for (j, &qc) in q.iter().enumerate() {
+            for (i, &pc) in p.iter().enumerate() {
+                product[i + j] ^= self.mul(pc, qc);
+            }
+        }
+        product
+    }
+
+    fn poly_eval(&self, p: &[u8], x: u8) -> u8 {
+        p.iter().fold(0, |acc, &c| self.mul(acc, x) ^ c)
+    }
+}
+
+/// Codes that have been built, with their number of parity symbols and parameters. Building the
+/// tables of a field for every correction is too slow, so each code is built the first time it is
+/// needed and kept for the lifetime of the process, since blocks may use it at any time.
+static CODECS: SpinLock<Vec<(usize, RsParams, &'static Codec)>> = SpinLock::new(Vec::new());
+
+/// The code with `n_ecc` parity symbols for `params`, built the first time it is asked for, or
+/// `None` if `Codec::new` would fail.
+pub fn codec(n_ecc: usize, params: &RsParams) -> Option<&'static Codec> {
+    let mut codecs = CODECS.lock();
+    if let Some(&(_, _, codec)) = codecs.iter().find(|(n, p, _)| *n == n_ecc && p == params) {
+        return Some(codec);
+    }
+    let codec: &'static Codec = Box::leak(Box::new(Codec::new(n_ecc, params)?));
+    codecs.push((n_ecc, *params, codec));
+    Some(codec)
+}
+
+/// A Reed-Solomon code with `n_ecc` parity symbols per codeword.
+///
+/// Codewords are given as bytes, data first, and the first symbol is the highest degree
+/// coefficient, like the `reed_solomon` crate. Errors and erasures are corrected with
+/// Berlekamp-Massey and Forney's algorithm.
+pub struct Codec {
+    field: Field,
+    n_ecc: usize,
+    first_root: usize,
+    params: RsParams,
+    generator: Vec<u8>,
+}
+
+impl Codec {
+    /// The code with `n_ecc` parity symbols for `params`, or `None` if the polynomial is not
+    /// primitive or the codewords would have no room for data.
+    pub fn new(n_ecc: usize, params: &RsParams) -> Option<Self> {
+        let field = Field::new(params.symbol_bits, params.polynomial)?;
+        if !params.fits(n_ecc) {
+            return None;
+        }
+        let first_root = params.first_root as usize % field.order;
+        let mut generator = vec![1u8];
+        for root in 0..n_ecc {
+            let factor = [1, field.alpha_pow((root + first_root) as isize)];
+            generator = field.poly_mul(&generator, &factor);
+        }
+        Some(Codec { field, n_ecc, first_root, params: *params, generator })
+    }
+
+    /// The number of data bytes carried by each codeword.
+    pub fn chunk_len(&self) -> usize {
+        self.params.chunk_len(self.n_ecc)
+    }
+
+    /// The number of bytes the parity of each codeword is stored in.
+    pub fn ecc_len(&self) -> usize {
+        self.params.ecc_len(self.n_ecc)
+    }
+
+    /// The number of parity symbols of each codeword.
+    pub fn n_ecc(&self) -> usize {
+        self.n_ecc
+    }
+
+    /// Writes the parity of `data` to `ecc`, which is `ecc_len` bytes long.
+    pub fn encode(&self, data: &[u8], ecc: &mut [u8]) {
+        let mut msg = self.unpack_symbols(data, &[]);
+        msg.resize(msg.len() + self.n_ecc, 0);
+        let n_data = msg.len() - self.n_ecc;
+        let mut remainder = msg.clone();
+        for idx in 0..n_data {
+            let coef = remainder[idx];
+            if coef != 0 {
+                for (j, &g) in self.generator.iter().enumerate().skip(1) {
+                    remainder[idx + j] ^= self.field.mul(g, coef);
+                }
+            }
+        }
+        msg[n_data..].copy_from_slice(&remainder[n_data..]);
+        self.pack_symbols(&msg, data.len(), ecc);
+        zeroize::wipe(&mut msg);
+        zeroize::wipe(&mut remainder);
+    }
+
+    /// Whether a codeword of data bytes followed by `ecc_len` parity bytes has any errors.
+    pub fn is_corrupted(&self, codeword: &[u8]) -> bool {
+        let (data, ecc) = codeword.split_at(codeword.len() - self.ecc_len());
+        let mut msg = self.unpack_symbols(data, ecc);
+        let corrupted = self.syndromes(&msg).iter().any(|&s| s != 0);
+        zeroize::wipe(&mut msg);
+        corrupted
+    }
+
+    /// Corrects a codeword of data bytes followed by `ecc_len` parity bytes in place, given the
+    /// positions of bytes that are known to be suspect. Returns `None` if there are too many
+    /// errors, in which case `codeword` is left unchanged.
+    pub fn correct(&self, codeword: &mut [u8], erasures: &[u8]) -> Option<()> {
+        let n_data = codeword.len() - self.ecc_len();
+        let mut msg = self.unpack_symbols(&codeword[..n_data], &codeword[n_data..]);
+        let per_byte = self.params.symbols_per_byte();
+        let symbols: Vec<usize> = erasures
+            .iter()
+            .flat_map(|&pos| (0..per_byte).map(move |symbol| pos as usize * per_byte + symbol))
+            .filter(|&pos| pos < msg.len())
+            .collect();
+
+        let corrected = self.correct_symbols(&mut msg, &symbols);
+        if corrected.is_some() {
+            let (data, ecc) = codeword.split_at_mut(n_data);
+            self.pack_symbols(&msg, 0, data);
+            self.pack_symbols(&msg, n_data, ecc);
+        }
+        zeroize::wipe(&mut msg);
+        corrected
+    }
+
+    /// Unpacks data bytes and the parity symbols stored in `ecc` into one list of symbols.
+    fn unpack_symbols(&self, data: &[u8], ecc: &[u8]) -> Vec<u8> {
+        let mut symbols = Vec::with_capacity(self.field.order);
+        let mut unpack = |bytes: &[u8]| match self.params.symbol_bits {
+            8 => symbols.extend_from_slice(bytes),
+            _ => bytes.iter().for_each(|&byte| symbols.extend_from_slice(&[byte & 0xf, byte >> 4])),
+        };
+        unpack(data);
+        unpack(ecc);
+        let n_data = data.len() * self.params.symbols_per_byte();
+        // An odd number of GF(2^4) parity symbols leaves the last nibble unused
+        symbols.truncate(n_data + self.n_ecc.min(symbols.len() - n_data));
+        symbols
+    }
+
+    /// Packs `symbols`, starting from the symbols of the data byte `start`, into `bytes`. Nibbles
+    /// past the last symbol are zero.
+    fn pack_symbols(&self, symbols: &[u8], start: usize, bytes: &mut [u8]) {
+        let per_byte = self.params.symbols_per_byte();
+        let symbols = &symbols[start * per_byte..];
+        for (idx, byte) in bytes.iter_mut().enumerate() {
+            *byte = match per_byte {
+                1 => symbols[idx],
+                _ => {
+                    let low = symbols.get(2 * idx).copied().unwrap_or(0);
+                    let high = symbols.get(2 * idx + 1).copied().unwrap_or(0);
+                    low | (high << 4)
+                }
+            };
+        }
+    }
+
+    fn syndromes(&self, msg: &[u8]) -> Vec<u8> {
+        (0..self.n_ecc)
+            .map(|idx| {
+                let root = self.field.alpha_pow((idx + self.first_root) as isize);
+                self.field.poly_eval(msg, root)
+            })
+            .collect()
+    }
+
+    /// Corrects a codeword of symbols in place, given the positions of symbols that are known to
+    /// be suspect. Returns `None` if there are too many errors, in which case `msg` holds garbage.
+    fn correct_symbols(&self, msg: &mut [u8], erasures: &[usize]) -> Option<()> {
+        if erasures.len() > self.n_ecc {
+            return None;
+        }
+        for &pos in erasures {
+            msg[pos] = 0;
+        }
+        let synd = self.syndromes(msg);
+        if synd.iter().all(|&s| s == 0) {
+            return Some(());
+        }
+
+        let forney = self.forney_syndromes(&synd, erasures, msg.len());
+        let mut err_loc = self.error_locator(&forney, erasures.len())?;
+        err_loc.reverse();
+        let mut positions = erasures.to_vec();
+        positions.extend(self.find_errors(&err_loc, msg.len())?);
+        self.correct_errata(msg, &synd, &positions)?;
+
+        match self.syndromes(msg).iter().any(|&s| s != 0) {
+            true => None,
+            false => Some(()),
+        }
+    }
+
+    /// The syndromes with the erasures taken out, so only the errors are left to be located.
+    fn forney_syndromes(&self, synd: &[u8], erasures: &[usize], len: usize) -> Vec<u8> {
+        let mut forney = synd.to_vec();
+        for &pos in erasures {
+            let x = self.field.alpha_pow((len - 1 - pos) as isize);
+            for j in 0..forney.len() - 1 {
+                forney[j] = self.field.mul(forney[j], x) ^ forney[j + 1];
+            }
+        }
+        forney
+    }
+
+    /// Berlekamp-Massey. Returns `None` if there are more errors than the parity can correct
+    /// alongside `n_erasures` erasures.
+    fn error_locator(&self, synd: &[u8], n_erasures: usize) -> Option<Vec<u8>> {
+        let mut err_loc = vec![1u8];
+        let mut old_loc = vec![1u8];
+        for k in 0..self.n_ecc - n_erasures {
+            let mut delta = synd[k];
+            for j in 1..err_loc.len().min(k + 1) {
+                delta ^= self.field.mul(err_loc[err_loc.len() - 1 - j], synd[k - j]);
+            }
+            old_loc.push(0);
+            if delta != 0 {
+                if old_loc.len() > err_loc.len() {
+                    let new_loc = self.field.poly_scale(&old_loc, delta);
+                    old_loc = self.field.poly_scale(&err_loc, self.field.inv(delta));
+                    err_loc = new_loc;
+                }
+                err_loc = self.field.poly_add(&err_loc, &self.field.poly_scale(&old_loc, delta));
+            }
+        }
+        let leading = err_loc.iter().take_while(|&&c| c == 0).count();
+        err_loc.drain(..leading);
+
+        let n_errors = err_loc.len().saturating_sub(1);
+        match 2 * n_errors + n_erasures > self.n_ecc {
+            true => None,
+            false => Some(err_loc),
+        }
+    }
+
+    /// Chien search for the roots of the (reversed) error locator, as positions in the codeword.
+    fn find_errors(&self, err_loc: &[u8], len: usize) -> Option<Vec<usize>> {
+        let positions: Vec<usize> = (0..len)
+            .filter(|&idx| self.field.poly_eval(err_loc, self.field.alpha_pow(idx as isize)) == 0)
+            .map(|idx| len - 1 - idx)
+            .collect();
+        match positions.len() == err_loc.len() - 1 {
+            true => Some(positions),
+            false => None,
+        }
+    }
+
+    /// Forney's algorithm: computes the error values at `positions` and applies them.
+    fn correct_errata(&self, msg: &mut [u8], synd: &[u8], positions: &[usize]) -> Option<()> {
+        let f = &self.field;
+        let coef_pos: Vec<usize> = positions.iter().map(|&pos| msg.len() - 1 - pos).collect();
+        let mut err_loc = vec![1u8];
+        for &coef in &coef_pos {
+            err_loc = f.poly_mul(&err_loc, &[f.alpha_pow(coef as isize), 1]);
+        }
+
+        // The evaluator is the syndrome polynomial times the locator, modulo x^(errors + 1)
+        let mut synd_rev: Vec<u8> = synd.iter().rev().copied().collect();
+        synd_rev.push(0);
+        let product = f.poly_mul(&synd_rev, &err_loc);
+        let err_eval = &product[product.len() - err_loc.len()..];
+
+        let xs: Vec<u8> = coef_pos.iter().map(|&coef| f.alpha_pow(coef as isize)).collect();
+        for (idx, &x) in xs.iter().enumerate() {
+            let x_inv = f.inv(x);
+            let err_loc_prime = xs
+                .iter()
+                .enumerate()
+                .filter(|&(other, _)| other != idx)
+                .fold(1, |acc, (_, &other)| f.mul(acc, 1 ^ f.mul(x_inv, other)));
+            if err_loc_prime == 0 {
+                return None;
+            }
+            let y = f.mul(
+                f.pow(x, 1 - self.first_root as isize),
+                f.poly_eval(err_eval, x_inv),
+            );
+            msg[positions[idx]] ^= f.div(y, err_loc_prime);
+        }
+        Some(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_field_check() {
+        // The default parameters give the same parity as the reed_solomon crate
+        let codec = Codec::new(8, &DEFAULT_PARAMS).unwrap();
+        let data: Vec<u8> = (0..100u32).map(|idx| (idx * 37 + 11) as u8).collect();
+        let mut ecc = [0u8; 8];
+        codec.encode(&data, &mut ecc);
+        assert_eq!(&ecc[..], reed_solomon::Encoder::new(8).encode(&data).ecc());
+
+        let mut codeword = data.clone();
+        codeword.extend_from_slice(&ecc);
+        assert!(!codec.is_corrupted(&codeword));
+
+        // Three errors and two erasures take all 8 parity symbols
+        let mut received = codeword.clone();
+        received[3] ^= 0xff;
+        received[50] ^= 0x01;
+        received[104] ^= 0x80;
+        received[10] = 0;
+        received[20] ^= 0x05;
+        assert!(codec.is_corrupted(&received));
+        assert_eq!(codec.correct(&mut received, &[10, 20]), Some(()));
+        assert_eq!(received, codeword);
+
+        // Five errors are too many, and the codeword is left alone
+        for pos in [1, 2, 3, 4, 5].iter() {
+            received[*pos] ^= 0x40;
+        }
+        let before = received.clone();
+        assert_eq!(codec.correct(&mut received, &[]), None);
+        assert_eq!(received, before);
+    }
+
+    #[test]
+    fn custom_field_check() {
+        // The field and generator roots of CCSDS, without its dual basis
+        let ccsds = RsParams { symbol_bits: 8, polynomial: 0x187, first_root: 112 };
+        let nibbles = RsParams { symbol_bits: 4, polynomial: 0x13, first_root: 1 };
+
+        for &(params, n_ecc, errors) in [(ccsds, 16, 8), (nibbles, 4, 2), (nibbles, 3, 1)].iter() {
+            let codec = Codec::new(n_ecc, &params).unwrap();
+            let data: Vec<u8> = (0..codec.chunk_len()).map(|idx| (idx * 91 + 7) as u8).collect();
+            let mut codeword = data.clone();
+            codeword.resize(data.len() + codec.ecc_len(), 0);
+            let (chunk_data, chunk_ecc) = codeword.split_at_mut(data.len());
+            codec.encode(chunk_data, chunk_ecc);
+
+            // The codeword has its roots where asked, and not where the default would put them
+            let symbols = codec.unpack_symbols(&codeword[..data.len()], &codeword[data.len()..]);
+            assert!(codec.syndromes(&symbols).iter().all(|&s| s == 0));
+            let shifted = RsParams { first_root: params.first_root + 1, ..params };
+            assert!(Codec::new(n_ecc, &shifted).unwrap().is_corrupted(&codeword));
+
+            // A flipped bit in each of the first `errors` symbols
+            let mut received = codeword.clone();
+            for symbol in 0..errors {
+                received[symbol * params.symbol_bits as usize / 8] ^= 1 << (symbol * 4 % 8);
+            }
+            assert!(codec.is_corrupted(&received));
+            assert_eq!(codec.correct(&mut received, &[]), Some(()));
+            assert_eq!(received, codeword);
+        }
+
+        // x does not generate the field of 0x11b or 0x1f, and GF(2^4) codewords have 15 symbols
+        assert!(Codec::new(4, &RsParams { polynomial: 0x11b, ..DEFAULT_PARAMS }).is_none());
+        assert!(Codec::new(2, &RsParams { polynomial: 0x1f, ..nibbles }).is_none());
+        assert!(Codec::new(14, &nibbles).is_none());
+    }
+
+    #[test]
+    fn codec_cache_check() {
+        // A code is built once per parameter set, and codes that cannot be built are not kept
+        let nibbles = RsParams { symbol_bits: 4, polynomial: 0x13, first_root: 1 };
+        let first = codec(4, &nibbles).unwrap();
+        assert!(core::ptr::eq(first, codec(4, &nibbles).unwrap()));
+        assert!(!core::ptr::eq(first, codec(2, &nibbles).unwrap()));
+        assert!(codec(14, &nibbles).is_none());
+    }
+}
@@ -0,0 +1,125 @@
+extern crate core;
+
+use core::ops::AddAssign;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+
+use libc::{self, c_void, pthread_t};
+
+use crate::error::ErError;
+use crate::policies::scrub_blocks;
+use crate::sync::SpinLock;
+
+/// Running totals of what the scrubber has done. The layout must match
+/// `struct er_scrub_stats` in `ermalloc.h`.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct ScrubStats {
+    /// The number of times every live block was reached.
+    pub passes: u64,
+    /// The number of blocks that were corrected.
+    pub blocks: u64,
+    /// The size of those blocks, including their parity and copies.
+    pub bytes: u64,
+    /// The number of errors that were corrected, as counted by their policies.
+    pub corrected: u64,
+    /// The number of times a block had more errors than its policies could correct.
+    pub uncorrectable: u64,
+    /// The number of times a block was skipped because another thread held it.
+    pub busy: u64,
+}
+
+impl AddAssign for ScrubStats {
+    fn add_assign(&mut self, other: ScrubStats) {
+        self.passes += other.passes;
+        self.blocks += other.blocks;
+        self.bytes += other.bytes;
+        self.corrected += other.corrected;
+        self.uncorrectable += other.uncorrectable;
+        self.busy += other.busy;
+    }
+}
+
+static STATS: SpinLock<ScrubStats> = SpinLock::new(ScrubStats {
+    passes: 0,
+    blocks: 0,
+    bytes: 0,
+    corrected: 0,
+    uncorrectable: 0,
+    busy: 0,
+});
+
+/// The scrubber thread, while it runs.
+static THREAD: SpinLock<Option<pthread_t>> = SpinLock::new(None);
+
+static STOP: AtomicBool = AtomicBool::new(false);
+static INTERVAL_MS: AtomicU32 = AtomicU32::new(0);
+static BYTES_PER_TICK: AtomicUsize = AtomicUsize::new(0);
+
+/// How long the scrubber sleeps at a time, so it notices soon enough that it was stopped.
+const SLEEP_STEP_MS: u32 = 10;
+
+/// Starts a thread that scrubs up to `bytes_per_tick` bytes of blocks (0 for every block)
+/// every `interval_ms` milliseconds, see `scrub_blocks`. If it is already running, it carries
+/// on with the new settings from its next tick.
+pub fn start(interval_ms: u32, bytes_per_tick: usize) -> Result<(), ErError> {
+    let mut thread = THREAD.lock();
+    INTERVAL_MS.store(interval_ms, Ordering::Relaxed);
+    BYTES_PER_TICK.store(bytes_per_tick, Ordering::Relaxed);
+    if thread.is_some() {
+        return Ok(());
+    }
+
+    STOP.store(false, Ordering::Relaxed);
+    let mut handle: pthread_t = 0;
+    match unsafe { libc::pthread_create(&mut handle, ptr::null(), run, ptr::null_mut()) } {
+        0 => {
+            *thread = Some(handle);
+            Ok(())
+        }
+        _ => Err(ErError::ThreadFailed),
+    }
+}
+
+/// Stops the scrubber thread, once it is done with the blocks it is on, and waits for it.
+/// Does nothing if it is not running.
+pub fn stop() {
+    let mut thread = THREAD.lock();
+    if let Some(handle) = thread.take() {
+        STOP.store(true, Ordering::Relaxed);
+        unsafe { libc::pthread_join(handle, ptr::null_mut()) };
+    }
+}
+
+/// What the scrubber has done since the process started.
+pub fn stats() -> ScrubStats {
+    *STATS.lock()
+}
+
+extern "C" fn run(_: *mut c_void) -> *mut c_void {
+    let mut below = None;
+    while !STOP.load(Ordering::Relaxed) {
+        let (mut tick, resume) = scrub_blocks(below, BYTES_PER_TICK.load(Ordering::Relaxed));
+        if resume.is_none() {
+            tick.passes += 1;
+        }
+        *STATS.lock() += tick;
+        below = resume;
+        sleep(INTERVAL_MS.load(Ordering::Relaxed));
+    }
+    ptr::null_mut()
+}
+
+/// Sleeps for `ms` milliseconds, or until the scrubber is stopped.
+fn sleep(ms: u32) {
+    if ms == 0 {
+        unsafe { libc::sched_yield() };
+    }
+    let mut left = ms;
+    while left > 0 && !STOP.load(Ordering::Relaxed) {
+        let step = left.min(SLEEP_STEP_MS);
+        let time = libc::timespec { tv_sec: 0, tv_nsec: step as libc::c_long * 1_000_000 };
+        unsafe { libc::nanosleep(&time, ptr::null_mut()) };
+        left -= step;
+    }
+}
@@ -0,0 +1,165 @@
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+
+/// The type of the elements of a buffer whose copies are voted on element by element.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ElementType {
+    U16,
+    U32,
+    U64,
+    F32,
+    F64,
+}
+
+/// How the copies of an element are voted on.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum VoteMode {
+    /// The value held by more than half of the copies.
+    Majority,
+    /// The median of the copies, or the lower of the two in the middle for an even number of
+    /// copies, so the outcome is always a value one of the copies held.
+    Median,
+    /// The median of the largest group of copies that are within `epsilon` of one of them, if
+    /// that is more than half of the copies.
+    Tolerance,
+}
+
+/// Voting on the copies of a buffer as elements of a type, rather than bit by bit.
+///
+/// A bitwise vote can produce a value that none of the copies held, such as a float with the
+/// exponent of one copy and the mantissa of another. Every mode here picks the value of one of
+/// the copies instead.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TypedVote {
+    pub element: ElementType,
+    pub mode: VoteMode,
+    /// How far apart two copies can be and still agree, for `VoteMode::Tolerance`.
+    pub epsilon: f64,
+}
+
+impl ElementType {
+    /// The size of an element in bytes.
+    pub fn size(self) -> usize {
+        match self {
+            ElementType::U16 => 2,
+            ElementType::U32 | ElementType::F32 => 4,
+            ElementType::U64 | ElementType::F64 => 8,
+        }
+    }
+
+    /// Reads an element from its little endian bytes.
+    pub fn read(self, bytes: &[u8]) -> u64 {
+        let mut word = [0u8; 8];
+        word[..self.size()].copy_from_slice(&bytes[..self.size()]);
+        u64::from_le_bytes(word)
+    }
+
+    /// Orders two elements by value. NaNs are ordered after (or before, if negative) every
+    /// number, so they end up at the ends rather than in the middle.
+    fn cmp(self, a: u64, b: u64) -> Ordering {
+        match self {
+            ElementType::F32 => f32::from_bits(a as u32).total_cmp(&f32::from_bits(b as u32)),
+            ElementType::F64 => f64::from_bits(a).total_cmp(&f64::from_bits(b)),
+            _ => a.cmp(&b),
+        }
+    }
+
+    /// Whether two elements are at most `epsilon` apart. A NaN is not close to anything.
+    fn close(self, a: u64, b: u64, epsilon: f64) -> bool {
+        let diff = match self {
+            ElementType::F32 => f32::from_bits(a as u32) as f64 - f32::from_bits(b as u32) as f64,
+            ElementType::F64 => f64::from_bits(a) - f64::from_bits(b),
+            _ => return a.max(b) - a.min(b) <= epsilon as u64,
+        };
+        -epsilon <= diff && diff <= epsilon
+    }
+}
+
+impl TypedVote {
+    /// Picks the copy that holds the outcome of the vote, given the value of an element in each
+    /// copy. Returns `None` if no value has the support of more than half of the copies.
+    pub fn winner(&self, values: &[u64]) -> Option<usize> {
+        let n_copies = values.len();
+        match self.mode {
+            VoteMode::Majority => (0..n_copies)
+                .find(|&copy| values.iter().filter(|&&v| v == values[copy]).count() * 2 > n_copies),
+            VoteMode::Median => self.median(values, (0..n_copies).collect()),
+            VoteMode::Tolerance => {
+                let support = |copy: usize| {
+                    values
+                        .iter()
+                        .filter(|&&v| self.element.close(values[copy], v, self.epsilon))
+                        .count()
+                };
+                // The first copy with the most support, so ties go the same way every time
+                let best = (0..n_copies).fold(None, |best: Option<(usize, usize)>, copy| {
+                    match best {
+                        Some((_, n)) if n >= support(copy) => best,
+                        _ => Some((copy, support(copy))),
+                    }
+                });
+                match best {
+                    Some((copy, n)) if n * 2 > n_copies => {
+                        let group = (0..n_copies)
+                            .filter(|&other| {
+                                self.element.close(values[copy], values[other], self.epsilon)
+                            })
+                            .collect();
+                        self.median(values, group)
+                    }
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    /// The copy with the median value among `copies`, or the lower of the two in the middle.
+    fn median(&self, values: &[u64], mut copies: Vec<usize>) -> Option<usize> {
+        copies.sort_by(|&a, &b| self.element.cmp(values[a], values[b]));
+        copies.get((copies.len().max(1) - 1) / 2).copied()
+    }
+
+    /// Whether a copy agrees with the outcome of the vote: exactly, or within `epsilon` for
+    /// `VoteMode::Tolerance`.
+    pub fn agrees(&self, outcome: u64, value: u64) -> bool {
+        match self.mode {
+            VoteMode::Tolerance => self.element.close(outcome, value, self.epsilon),
+            _ => outcome == value,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn typed_vote_check() {
+        let bits = |values: &[f32]| -> Vec<u64> { values.iter().map(|v| v.to_bits() as u64).collect() };
+        let vote = |mode, epsilon| TypedVote { element: ElementType::F32, mode, epsilon };
+
+        // Every copy is different, so there is no majority, but there is a median
+        let values = bits(&[20.5, 20.25, 1e30]);
+        assert_eq!(vote(VoteMode::Majority, 0.0).winner(&values), None);
+        assert_eq!(vote(VoteMode::Median, 0.0).winner(&values), Some(0));
+        // The first two are close enough to agree, and the lower one is the median of the two
+        assert_eq!(vote(VoteMode::Tolerance, 0.5).winner(&values), Some(1));
+        assert_eq!(vote(VoteMode::Tolerance, 0.1).winner(&values), None);
+
+        // A NaN is never the median, and never close to anything
+        let values = bits(&[f32::NAN, 3.0, 3.0, 2.0]);
+        assert_eq!(vote(VoteMode::Median, 0.0).winner(&values), Some(1));
+        assert_eq!(vote(VoteMode::Majority, 0.0).winner(&values), None);
+        assert_eq!(vote(VoteMode::Tolerance, 1.0).winner(&values), Some(1));
+        assert!(!vote(VoteMode::Tolerance, 1.0).agrees(values[1], values[0]));
+
+        // Integers are compared as unsigned
+        let ints = TypedVote { element: ElementType::U16, mode: VoteMode::Median, epsilon: 0.0 };
+        assert_eq!(ints.winner(&[0xffff, 7, 9]), Some(2));
+    }
+}
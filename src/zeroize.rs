@@ -0,0 +1,112 @@
+extern crate alloc;
+extern crate core;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem::{self, ManuallyDrop};
+use core::ops::{Deref, DerefMut};
+use core::ptr;
+use core::sync::atomic::{compiler_fence, Ordering};
+
+/// Overwrites `buf` with zeros.
+///
+/// The writes are volatile, so the compiler cannot drop them even though the memory is about to be
+/// freed or go out of scope.
+pub fn wipe(buf: &mut [u8]) {
+    fill(buf, 0);
+}
+
+/// Overwrites `buf` with `byte`, with the same guarantees as `wipe`.
+pub fn fill(buf: &mut [u8], byte: u8) {
+    for dest in buf.iter_mut() {
+        unsafe { ptr::write_volatile(dest, byte) };
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+/// Holds a value with key material in it, such as a cipher and its expanded round keys, and
+/// wipes it with the same guarantees as `wipe` when it is dropped.
+///
+/// The value is wiped in place of being dropped, so it must not own any memory of its own.
+#[repr(transparent)]
+pub struct Wiped<T>(ManuallyDrop<T>);
+
+impl<T> Wiped<T> {
+    pub fn new(value: T) -> Self {
+        Wiped(ManuallyDrop::new(value))
+    }
+}
+
+impl<T> Deref for Wiped<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Wiped<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T> Drop for Wiped<T> {
+    fn drop(&mut self) {
+        let bytes = &mut *self.0 as *mut T as *mut u8;
+        for idx in 0..mem::size_of::<T>() {
+            unsafe { ptr::write_volatile(bytes.add(idx), 0) };
+        }
+        compiler_fence(Ordering::SeqCst);
+    }
+}
+
+/// A heap buffer for temporary plaintext that is wiped when it is dropped.
+///
+/// It is allocated once at its final size, so no stale copies are left behind by reallocation.
+pub struct Scratch(Vec<u8>);
+
+impl Scratch {
+    pub fn from(data: &[u8]) -> Self {
+        Scratch(Vec::from(data))
+    }
+
+    /// A scratch buffer of `len` zeros.
+    pub fn zeroed(len: usize) -> Self {
+        Scratch(vec![0; len])
+    }
+}
+
+impl Deref for Scratch {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl DerefMut for Scratch {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+}
+
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        wipe(&mut self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::mem::MaybeUninit;
+
+    #[test]
+    fn wiped_check() {
+        let mut slot = MaybeUninit::new(Wiped::new([0xa5u8; 32]));
+        unsafe { ptr::drop_in_place(slot.as_mut_ptr()) };
+        let bytes = unsafe { &*(slot.as_ptr() as *const [u8; 32]) };
+        assert_eq!(bytes, &[0u8; 32]);
+    }
+}
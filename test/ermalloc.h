@@ -1,12 +1,186 @@
 #include <stdlib.h>
+#include <stdint.h>
 
-#define MAX_POLICIES (3)
+#define MAX_POLICIES (4)
+/* The most copies a SeparatedRedundancy block can have, including the first */
+#define MAX_SEPARATED_COPIES (5)
 
 enum er_policy {
     Nil = 0,
     Redundancy,
     ReedSolomon, 
-    Encrypted
+    Encrypted,
+    Authenticated,
+    /* Cannot be combined with ReedSolomon or its variants */
+    SecDed,
+    /* Detection only, with the CRC width (32 or 64) as policy_data. Covers ReedSolomon or
+     * SecDed if one is given, and is checked once they have corrected the data */
+    Checksum,
+    /* Interleaves the codewords of ReedSolomon, with the longest burst of adjacent bytes to
+     * spread out (at most one error per codeword) as policy_data. Uses the default parity
+     * if ReedSolomon is not given. Does not count towards MAX_POLICIES. Only the codewords
+     * within each copy are interleaved: the redundant copies of Redundancy stay one after
+     * the other, since interleaving them would put the copies of a byte next to each other */
+    Interleaved,
+    /* Stores the bits of every word that are in a mask twice more and votes on them, with a
+     * struct er_significance as policy_data. The other bits are left to the other policies.
+     * Cannot be combined with Encrypted or Authenticated: the allocation fails with EINVAL */
+    UnequalProtection,
+    /* Same as ReedSolomon, but parity that no longer matches the data is encoded again
+     * instead of decoded, when the data cannot be at fault */
+    DataOnlyReedSolomon,
+    /* Same as SecDed, but any odd number of flips that does not point at a data bit is taken
+     * to be in the check byte, which is encoded again */
+    DataOnlySecDed,
+    /* A binary linear code from a parity-check matrix, with the id of the code (from
+     * er_register_linear_code, or a built in one) as policy_data */
+    LinearCode,
+    /* Same as ReedSolomon, over the field and generator in a struct er_rs_field given as
+     * policy_data */
+    ReedSolomonField,
+    /* Same as Redundancy, but every copy is followed by its own CRC-32, and the first copy
+     * that matches it is used. Copies are only voted on if none of them do, so an even number
+     * of copies can be used. Takes the place of Redundancy; the one given last is used */
+    CheckedRedundancy,
+    /* Same as Redundancy, but the copies are voted on element by element as numbers, with a
+     * struct er_typed_vote as policy_data, so the outcome is always a value one of the copies
+     * held. Takes the place of Redundancy; the one given last is used */
+    TypedRedundancy,
+    /* Same as Redundancy, but every copy after the first is in an allocation of its own, with
+     * a struct er_separation as policy_data (3 copies and 1 guard page if NULL). Takes the
+     * place of Redundancy; the one given last is used */
+    SeparatedRedundancy
+};
+
+/**
+ * Codes that are built in, for LinearCode
+ */
+enum er_builtin_code {
+    /* Corrects any single flip and two adjacent flips in a 32 bit word, with 7 check bits */
+    ER_CODE_SEC_DAED_39_32 = 0
+};
+
+/**
+ * The policy_data of UnequalProtection
+ */
+struct er_significance {
+    /* Width of the words in bits: 8, 16, 32 or 64 */
+    unsigned int width;
+    /* The bits of each (little endian) word that get the stronger protection */
+    unsigned long long mask;
+};
+
+/**
+ * The policy_data of ReedSolomonField. ReedSolomon is the same as
+ * { n_ecc, 8, 0x11d, 0 }
+ */
+struct er_rs_field {
+    /* Parity symbols per codeword */
+    unsigned int n_ecc;
+    /* Bits per symbol: 8, or 4 for codewords of at most 15 symbols, two to a byte */
+    unsigned int symbol_bits;
+    /* Primitive polynomial of the field, including the x^symbol_bits term */
+    unsigned int polynomial;
+    /* Power of the primitive element that is the first root of the generator polynomial */
+    unsigned int first_root;
+};
+
+/**
+ * The type of the elements of a TypedRedundancy block
+ */
+enum er_element {
+    ER_ELEMENT_U16 = 0,
+    ER_ELEMENT_U32,
+    ER_ELEMENT_U64,
+    ER_ELEMENT_F32,
+    ER_ELEMENT_F64
+};
+
+/**
+ * How the copies of each element of a TypedRedundancy block are voted on
+ */
+enum er_vote_mode {
+    /* The value held by more than half of the copies */
+    ER_VOTE_MAJORITY = 0,
+    /* The median, or the lower of the two in the middle for an even number of copies */
+    ER_VOTE_MEDIAN,
+    /* The median of the largest group of copies within epsilon of one of them, if that is
+     * more than half of the copies */
+    ER_VOTE_TOLERANCE
+};
+
+/**
+ * The policy_data of TypedRedundancy. It is required, and the allocation fails with errno set
+ * to EINVAL if it is NULL or holds values out of range
+ */
+struct er_typed_vote {
+    /* Total number of copies, including the data, at least 1 */
+    unsigned int n_copies;
+    enum er_element element;
+    enum er_vote_mode mode;
+    /* How far apart copies can be and still agree, for ER_VOTE_TOLERANCE. Must not be
+     * negative or NaN */
+    double epsilon;
+};
+
+/**
+ * The policy_data of SeparatedRedundancy
+ */
+struct er_separation {
+    /* Total number of copies, including the data, up to MAX_SEPARATED_COPIES */
+    unsigned int n_copies;
+    /* Inaccessible pages before and after each separated copy, which then starts a page of
+     * its own. With 0 the copies come from the heap, and may share pages */
+    unsigned int guard_pages;
+};
+
+/**
+ * Unrecoverable errors, returned as negative values
+ */
+enum er_error {
+    /* Authenticated data did not match its tag after correction */
+    ER_TAG_MISMATCH = -1,
+    /* Data is encrypted under a key that has been retired */
+    ER_UNKNOWN_KEY = -2,
+    /* A key of the wrong length was supplied */
+    ER_INVALID_KEY = -3,
+    /* The key provider could not supply the key */
+    ER_KEY_UNAVAILABLE = -4,
+    /* Too many copies of the stored key were corrupted to recover it */
+    ER_UNRECOVERABLE_KEY = -5,
+    /* Locked memory could not be allocated, usually because the mlock limit was reached.
+     * The functions that allocate blocks return NULL instead, with errno set by mlock */
+    ER_LOCK_FAILED = -6,
+    /* The data has more errors than its policies can correct */
+    ER_UNCORRECTABLE = -7,
+    /* A parity-check matrix does not describe a code that can correct single errors */
+    ER_INVALID_CODE = -8,
+    /* The memory for a block could not be allocated */
+    ER_ALLOC_FAILED = -9,
+    /* The metadata of the block and its backup were both corrupted, so it cannot be used */
+    ER_CORRUPT_HEADER = -10,
+    /* The pointer was not returned by er_malloc and friends, or points into the middle of a
+     * block. Every function that takes a block checks for this */
+    ER_INVALID_POINTER = -11,
+    /* The block was already freed: a double free, or a use after free. This is caught until
+     * the memory is reused */
+    ER_ALREADY_FREED = -12,
+    /* Two links of the list of live blocks were corrupted, so the blocks between them could
+     * not be reached. The list is cut short, and the blocks are no longer tracked */
+    ER_CORRUPT_REGISTRY = -13,
+    /* A thread could not be started */
+    ER_THREAD_FAILED = -14
+};
+
+/**
+ * Where a block's memory comes from, given as the policy_data of an Encrypted or
+ * Authenticated policy. Locked memory is never swapped out and is left out of core dumps.
+ */
+enum er_lock_mode {
+    /* Locked if the block is encrypted, unless disabled with er_lock_encrypted (same as NULL) */
+    ER_LOCK_DEFAULT = 0,
+    ER_LOCK_LOCKED,
+    ER_LOCK_UNLOCKED
 };
 
 struct er_policy_list {
@@ -23,21 +197,50 @@ void* realloc(void* ptr, size_t size);
 void* reallocarray(void* ptr, size_t nmemb, size_t size);
 */
 /**
- * Allocate uninitialized memory
+ * Allocate uninitialized memory. Encrypted and Authenticated blocks are zeroed and
+ * encrypted instead, so they can be read or partially written before they are first written
  *
  * @param policies policies for the region, NULL for no policies
+ * @return NULL with errno set by mlock (ENOMEM, or EAGAIN or EPERM, when RLIMIT_MEMLOCK is
+ *         reached) if the block needed locked memory that could not be locked, or set to
+ *         EINVAL if the policy data of an encryption policy is not an enum er_lock_mode or
+ *         the list combines policies that cannot be layered, such as SecDed and ReedSolomon,
+ *         or UnequalProtection and Encrypted, or a struct er_typed_vote is out of range
  */
 void* er_malloc(size_t size, const struct er_policy_list* policies);
 
 /**
- * Same as free
+ * Same as free. Freed blocks are poisoned (filled with 0xdf), which also wipes them, and
+ * marked as freed. A block whose header is corrupted beyond repair is leaked, since its size
+ * is not known.
+ *
+ * @return = 0 on success, or if ptr is NULL
+ *         < 0 ER_ALREADY_FREED on a double free, ER_INVALID_POINTER if ptr was not returned by
+ *             er_malloc and friends, or ER_CORRUPT_HEADER
  */
-void  er_free(void* ptr);
+int   er_free(void* ptr);
+
+/**
+ * Choose whether encrypted blocks use locked memory when their policy does not say
+ *
+ * @param enabled nonzero to lock them (the default), 0 to use the regular heap
+ */
+void  er_lock_encrypted(int enabled);
+
+/**
+ * Kept for compatibility: every block is now poisoned, and so wiped, before it is freed
+ *
+ * @deprecated Does nothing, since wiping on free can no longer be turned off
+ * @param enabled ignored
+ */
+__attribute__((deprecated("every block is wiped on free")))
+void  er_wipe_all_on_free(int enabled);
 
 /**
  * Allocate memory and zero it out
  *
  * @param policies policies for the region, NULL for no policies
+ * @return NULL with errno set if locked memory could not be allocated, as for er_malloc
  */
 void* er_calloc(size_t nmemb, size_t size, const struct er_policy_list* policies);
 
@@ -46,6 +249,10 @@ void* er_calloc(size_t nmemb, size_t size, const struct er_policy_list* policies
  *
  * @param policies The policies to apply to the newly allocated block
  * Any original policies will be used to maintain data integrity while moving the allocation
+ * A NULL ptr allocates a new block, like er_malloc
+ * @return NULL if the original data could not be decrypted, the original block is left untouched,
+ *         or if its header is corrupted beyond repair, or if ptr is not a live block, or with
+ *         errno set if locked memory could not be allocated, as for er_malloc
  */
 void* er_realloc(void* ptr, size_t size, const struct er_policy_list* policies);
 
@@ -68,23 +275,62 @@ void er_change_policies(void* ptr, const struct er_policy_list* policies);
  * 2. Call er_setup_policies to initialize the policies on your data
  * 3. Further calls to er_read/write_buf will correct this bits
  * 4. You can also use er_correct_buffer to manually apply corrections
+ *
+ * @return = 0 on success
+ *         < 0 ER_KEY_UNAVAILABLE if the data could not be encrypted
  */
-void er_setup_policies(const void* ptr);
+int er_setup_policies(const void* ptr);
 
 /**
  * Use policies to find bit errors and correct them if possible and desired
  *
  * @return = 0 if no errors
  *         < 0 if unrecoverable errors, as defined by the associated policies
+ *             (ER_UNCORRECTABLE if a code detected errors it could not correct and no
+ *             redundant copy outvoted it, e.g. two flipped bits in one SecDed word, or
+ *             ER_CORRUPT_HEADER if the metadata of the block cannot be repaired). A
+ *             ReedSolomon codeword with too many errors is left as it is instead, see
+ *             er_codeword_errors
  *         > 0 number of errors found/corrected, as defined by the associated policies
  */
 int er_correct_buffer(void* ptr);
 
+/**
+ * Same as er_correct_buffer, but also reports how many symbols were corrected in each
+ * Reed-Solomon codeword. Buffers are split into codewords of at most 255 bytes
+ * (data + parity), and every codeword carries its own parity. For SecDed, every
+ * 8 byte word is a codeword.
+ *
+ * @param counts Array receiving one entry per codeword, in buffer order.
+ *               Each entry is the number of corrected symbols, or -1 if the
+ *               codeword could not be corrected. May be NULL.
+ * @param max_counts Number of entries available in counts
+ * @return The total number of codewords, which may exceed max_counts,
+ *         or ER_CORRUPT_HEADER
+ */
+int er_codeword_errors(void* ptr, int* counts, size_t max_counts);
+
+/**
+ * Correct the buffer and report which copies of a TypedRedundancy block
+ * disagreed with the outcome of the vote, for at least one element. Copies
+ * within epsilon of the outcome agree with it.
+ *
+ * @param copies Array receiving the indices of the copies that disagreed, in
+ *               increasing order. May be NULL.
+ * @param max_copies Number of entries available in copies
+ * @return >= 0 the number of copies that disagreed, which may exceed max_copies
+ *         < 0 ER_UNCORRECTABLE if an element had no winner, or ER_CORRUPT_HEADER. The
+ *             copies that disagreed on the other elements are still written to copies
+ */
+int er_disagreeing_copies(void* ptr, size_t* copies, size_t max_copies);
+
 /**
  * Enforce the policy and read the data
  * Depending on the policies selected,
  * the policy enforcement may act on the
- * entire allocated block, rather than just the desired region
+ * entire allocated block, rather than just the desired region.
+ * Encrypted data is decrypted straight into dest, so the block in the heap
+ * is never left as plaintext.
  *
  * @param base Pointer to start of allocation
  * @param dest Pointer to destination buffer
@@ -92,6 +338,7 @@ int er_correct_buffer(void* ptr);
  * @param len Number of bytes to read
  * @return = 0 if no errors
  *         < 0 if unrecoverable errors, as defined by the associated policies
+ *             (ER_TAG_MISMATCH if Authenticated data fails its tag, dest is left untouched)
  *         > 0 number of errors found/corrected, as defined by the associated policies
  */
 int er_read_buf(void* base, void* dest, size_t offset, size_t len);
@@ -109,3 +356,147 @@ int er_read_buf(void* base, void* dest, size_t offset, size_t len);
  */
 int er_write_buf(void* base, const void* src, size_t offset, size_t len);
 
+/**
+ * Make key the encryption key and re-encrypt every live Encrypted or Authenticated block under it.
+ * Each block is corrected, decrypted under its old key, then encrypted and protected
+ * again under the new key. The old key is kept until every block has moved to the new one.
+ *
+ * Each block is locked while it is moved, and blocks other threads are reading or writing
+ * are moved once they are done with them.
+ *
+ * @param key The AES-128 key
+ * @param len Length of key, must be 16
+ * @return >= 0 number of blocks re-encrypted
+ *         < 0 ER_INVALID_KEY if the key has the wrong length, or the error of a block that
+ *             could not be decrypted (it stays under its old key, which is kept through
+ *             later rotations until one of them moves the block)
+ */
+int er_set_key(const void* key, size_t len);
+
+/**
+ * Same as er_set_key with a fresh random key from the kernel
+ */
+int er_rotate_key(void);
+
+/**
+ * Writes len key bytes to key and returns 0, or returns nonzero if the key is unavailable.
+ * It is called every time a block is encrypted or decrypted, and must not call into ermalloc.
+ */
+typedef int (*er_key_callback)(void* ctx, unsigned char* key, size_t len);
+
+/**
+ * Same as er_set_key, but the key is fetched from callback whenever it is needed
+ * instead of being kept by the library (e.g. to keep it in a hardware security module).
+ *
+ * @param ctx Passed back to every call of callback
+ * @return >= 0 number of blocks re-encrypted
+ *         < 0 ER_KEY_UNAVAILABLE if callback fails, in which case it is not installed
+ */
+int er_register_key_provider(er_key_callback callback, void* ctx);
+
+/**
+ * Same as er_set_key with the key read from a file holding the 16 raw key bytes.
+ * The first key can also be read from a file by setting ERMALLOC_KEY_FILE. If that file
+ * cannot be read, encrypting fails with this function's error until another key is set.
+ *
+ * @return >= 0 number of blocks re-encrypted
+ *         < 0 ER_KEY_UNAVAILABLE if the file cannot be opened,
+ *             ER_INVALID_KEY if it holds fewer than 16 bytes
+ */
+int er_load_key_file(const char* path);
+
+/**
+ * Build a binary linear code from its parity-check matrix H, for the LinearCode policy.
+ * Every single flipped bit is corrected, and so are two adjacent flipped bits when no other
+ * single or adjacent error has the same syndrome. Any other syndrome is reported as
+ * ER_UNCORRECTABLE. Codes are kept until the process exits.
+ *
+ * @param columns The columns of H, with row i in bit i. The data columns come first, followed by
+ *                the check columns, which must be the identity matrix
+ * @param n_columns Number of columns, at most 32 more than data_bits
+ * @param data_bits Number of data bits per word, a multiple of 8 up to 64. Each word is stored
+ *                  with its check bits rounded up to whole bytes after the data
+ * @return >= 0 the id of the code, to use as the policy_data of LinearCode
+ *         < 0 ER_INVALID_CODE if single errors cannot all be told apart, a data column has a
+ *             row past the check bits, or the sizes are wrong
+ */
+int er_register_linear_code(const unsigned int* columns, size_t n_columns, size_t data_bits);
+
+/**
+ * A live block, as passed to the callback of er_for_each_block
+ */
+struct er_block_info {
+    /* The pointer returned by er_malloc and friends */
+    void* ptr;
+    /* The size that was asked for */
+    size_t length;
+    /* The size of the data and everything its policies added */
+    size_t buffer_size;
+    /* Unique for the lifetime of the process */
+    uint64_t id;
+};
+
+/**
+ * Called on each live block by er_for_each_block. Returns 0 to go on to the next block, or
+ * nonzero to stop. The blocks are listed before the first call, so it may allocate and free
+ * blocks, and blocks freed by then are still passed to it.
+ */
+typedef int (*er_block_callback)(const struct er_block_info* info, void* ctx);
+
+/**
+ * Call callback on every live block, most recently allocated first.
+ * Corrupted links in the list of blocks are repaired on the way, and blocks whose metadata
+ * cannot be repaired are skipped.
+ *
+ * @param ctx Passed back to every call of callback
+ * @return >= 0 number of blocks callback was called on
+ *         < 0 ER_CORRUPT_REGISTRY if some blocks could not be reached, after callback was
+ *             called on the others
+ */
+int er_for_each_block(er_block_callback callback, void* ctx);
+
+/**
+ * Running totals of what the scrubber has done, since the process started
+ */
+struct er_scrub_stats {
+    /* Times every live block was reached */
+    uint64_t passes;
+    /* Blocks that were corrected */
+    uint64_t blocks;
+    /* Size of those blocks, including their parity and copies */
+    uint64_t bytes;
+    /* Errors that were corrected, as counted by er_correct_buffer */
+    uint64_t corrected;
+    /* Times a block had more errors than its policies could correct */
+    uint64_t uncorrectable;
+    /* Times a block was skipped because another thread was using it */
+    uint64_t busy;
+};
+
+/**
+ * Start a thread that corrects live blocks in the background, so errors in blocks that are
+ * rarely read are found before more pile up. Every interval_ms milliseconds it calls
+ * er_correct_buffer on blocks until they add up to bytes_per_tick bytes, then carries on from
+ * there on the next tick. Blocks that another thread is reading, writing or correcting are
+ * skipped until the next pass. If the scrubber is already running, it uses the new settings.
+ *
+ * Data written through the pointer directly is corrected back to the old data if the scrubber
+ * reaches the block before er_setup_policies is called. Use er_write_buf to write data to a
+ * block while the scrubber is running.
+ *
+ * @param bytes_per_tick At least one block is corrected every tick. With 0 every block is
+ * @return = 0 if the scrubber is running
+ *         < 0 ER_THREAD_FAILED if the thread could not be started
+ */
+int er_scrubber_start(unsigned int interval_ms, size_t bytes_per_tick);
+
+/**
+ * Stop the scrubber, and wait for it to finish the tick it is on. Does nothing if it is not
+ * running
+ */
+void er_scrubber_stop(void);
+
+/**
+ * Get the running totals of the scrubber
+ */
+void er_scrubber_stats(struct er_scrub_stats* stats);
@@ -1,6 +1,8 @@
 #include <stdlib.h>
 #include <stdio.h>
 #include <string.h>
+#include <errno.h>
+#include <unistd.h>
 
 #include "ermalloc.h"
 
@@ -89,6 +91,427 @@ void rs_test(void) {
     END_FUNC;
 }
 
+void secded_test(void) {
+
+    START_FUNC;
+
+    struct er_policy_list p = {
+        .policy = SecDed,
+        .policy_data = NULL,
+        .next_policy = NULL
+    };
+
+    long* x = er_malloc(2 * sizeof(long), &p);
+    x[0] = 0b1010;
+    x[1] = 7;
+    er_setup_policies(x);
+    x[0] ^= 1L << 40;
+    int r = er_correct_buffer(x);
+    printf("er_correct_buffer: %d, x[0] = %ld\n", r, x[0]);
+
+    // Two flips in one word are detected, but cannot be corrected
+    x[1] ^= 0b11;
+    r = er_correct_buffer(x);
+    printf("er_correct_buffer: %d (ER_UNCORRECTABLE = %d)\n", r, ER_UNCORRECTABLE);
+    er_free(x);
+
+    // SecDed and ReedSolomon cannot be layered
+    struct er_policy_list rs = {
+        .policy = ReedSolomon,
+        .policy_data = NULL,
+        .next_policy = NULL
+    };
+    p.next_policy = &rs;
+    x = er_malloc(2 * sizeof(long), &p);
+    printf("SecDed and ReedSolomon: %p, EINVAL: %d\n", (void*) x, errno == EINVAL);
+
+    END_FUNC;
+}
+
+void checksum_test(void) {
+
+    START_FUNC;
+
+    struct er_policy_list p = {
+        .policy = Checksum,
+        .policy_data = &(int){64},
+        .next_policy = NULL
+    };
+
+    int* x = er_malloc(4 * sizeof(int), &p);
+    int og_data[4] = {1, 2, 3, 4};
+    er_write_buf(x, &og_data, 0, sizeof(og_data));
+    int r = er_correct_buffer(x);
+    printf("er_correct_buffer: %d\n", r);
+
+    // Errors are detected, but never corrected
+    x[2] ^= 1 << 7;
+    r = er_correct_buffer(x);
+    printf("er_correct_buffer: %d (ER_UNCORRECTABLE = %d)\n", r, ER_UNCORRECTABLE);
+    er_free(x);
+
+    END_FUNC;
+}
+
+void interleaved_test(void) {
+
+    START_FUNC;
+
+    struct er_policy_list interleaved = {
+        .policy = Interleaved,
+        .policy_data = &(int){8},
+        .next_policy = NULL
+    };
+    struct er_policy_list p = {
+        .policy = ReedSolomon,
+        .policy_data = &(int){2},
+        .next_policy = &interleaved
+    };
+
+    char* x = er_malloc(64, &p);
+    for (int i = 0; i < 64; i++) {
+        x[i] = i;
+    }
+    er_setup_policies(x);
+
+    // A burst over 8 adjacent bytes is one error in each of the 8 codewords
+    memset(x + 20, 0xff, 8);
+    int counts[8];
+    int n = er_codeword_errors(x, counts, 8);
+    printf("er_codeword_errors: %d codewords:", n);
+    for (int i = 0; i < n && i < 8; i++) {
+        printf(" %d", counts[i]);
+    }
+    printf(", x[20] = %d, x[27] = %d\n", x[20], x[27]);
+    er_free(x);
+
+    END_FUNC;
+}
+
+void unequal_protection_test(void) {
+
+    START_FUNC;
+
+    // Fixed point readings, where only the top 4 bits are critical
+    struct er_significance significance = { .width = 16, .mask = 0xf000 };
+    struct er_policy_list p = {
+        .policy = UnequalProtection,
+        .policy_data = &significance,
+        .next_policy = NULL
+    };
+
+    short* x = er_malloc(4 * sizeof(short), &p);
+    short og_data[4] = {0x1234, 0x2345, 0x3456, 0x4567};
+    er_write_buf(x, &og_data, 0, sizeof(og_data));
+
+    x[1] ^= 0x4000;
+    int r = er_correct_buffer(x);
+    printf("er_correct_buffer: %d, x[1] = 0x%04hx\n", r, x[1]);
+
+    // Low bits are not protected on their own
+    x[1] ^= 0x0001;
+    r = er_correct_buffer(x);
+    printf("er_correct_buffer: %d, x[1] = 0x%04hx\n", r, x[1]);
+    er_free(x);
+
+    // Which bits are significant is lost once the data is encrypted
+    struct er_policy_list encrypted = {
+        .policy = Encrypted,
+        .policy_data = NULL,
+        .next_policy = NULL
+    };
+    p.next_policy = &encrypted;
+    x = er_malloc(4 * sizeof(short), &p);
+    printf("UnequalProtection and Encrypted: %p, EINVAL: %d\n", (void*) x, errno == EINVAL);
+
+    END_FUNC;
+}
+
+void data_only_test(void) {
+
+    START_FUNC;
+
+    struct er_policy_list p = {
+        .policy = DataOnlyReedSolomon,
+        .policy_data = &(int){4},
+        .next_policy = NULL
+    };
+
+    int* x = er_malloc(4 * sizeof(int), &p);
+    int og_data[4] = {1, 2, 3, 4};
+    er_write_buf(x, &og_data, 0, sizeof(og_data));
+
+    // The parity right after the data is encoded again, rather than decoded
+    ((char*)x)[sizeof(og_data) + 1] ^= 0x5a;
+    int r = er_correct_buffer(x);
+    printf("er_correct_buffer: %d\n", r);
+
+    x[2] ^= 1 << 3;
+    r = er_correct_buffer(x);
+    printf("er_correct_buffer: %d, x[2] = %d\n", r, x[2]);
+    er_free(x);
+
+    END_FUNC;
+}
+
+void linear_code_test(void) {
+
+    START_FUNC;
+
+    struct er_policy_list p = {
+        .policy = LinearCode,
+        .policy_data = &(int){ER_CODE_SEC_DAED_39_32},
+        .next_policy = NULL
+    };
+
+    int* x = er_malloc(2 * sizeof(int), &p);
+    int og_data[2] = {0x12345678, 42};
+    er_write_buf(x, &og_data, 0, sizeof(og_data));
+
+    // Two adjacent bits, as one particle strike would flip them
+    x[0] ^= 0b11 << 9;
+    int r = er_correct_buffer(x);
+    printf("er_correct_buffer: %d, x[0] = 0x%08x\n", r, x[0]);
+    er_free(x);
+
+    // A (12,8) Hamming code, given by its parity-check matrix
+    unsigned int columns[12] = {0x3, 0x5, 0x6, 0x7, 0x9, 0xa, 0xb, 0xc, 0x1, 0x2, 0x4, 0x8};
+    int id = er_register_linear_code(columns, 12, 8);
+    p.policy_data = &id;
+    char* y = er_malloc(4, &p);
+    er_write_buf(y, "abc", 0, 4);
+    y[1] ^= 1 << 2;
+    r = er_correct_buffer(y);
+    printf("er_register_linear_code: %d, er_correct_buffer: %d, y = %s\n", id, r, y);
+    er_free(y);
+
+    END_FUNC;
+}
+
+void rs_field_test(void) {
+
+    START_FUNC;
+
+    // GF(2^4), for an allocation too small to be worth 255 byte codewords
+    struct er_rs_field field = { .n_ecc = 4, .symbol_bits = 4, .polynomial = 0x13, .first_root = 1 };
+    struct er_policy_list p = {
+        .policy = ReedSolomonField,
+        .policy_data = &field,
+        .next_policy = NULL
+    };
+
+    char* x = er_malloc(5, &p);
+    er_write_buf(x, "tiny", 0, 5);
+    x[2] ^= 0xff;
+    int r = er_correct_buffer(x);
+    printf("er_correct_buffer: %d, x = %s\n", r, x);
+    er_free(x);
+
+    // The field and generator roots of CCSDS
+    field = (struct er_rs_field){ .n_ecc = 32, .symbol_bits = 8, .polynomial = 0x187, .first_root = 112 };
+    char* y = er_malloc(64, &p);
+    er_write_buf(y, "telemetry frame", 0, 16);
+    for (int i = 0; i < 16; i++) {
+        y[i] ^= 0x5a;
+    }
+    r = er_correct_buffer(y);
+    printf("er_correct_buffer: %d, y = %s\n", r, y);
+    er_free(y);
+
+    END_FUNC;
+}
+
+void checked_redundancy_test(void) {
+
+    START_FUNC;
+
+    struct er_policy_list p = {
+        .policy = CheckedRedundancy,
+        .policy_data = &(int){2},
+        .next_policy = NULL
+    };
+
+    int* x = er_malloc(sizeof(int), &p);
+    int og_data = 0b1010;
+    er_write_buf(x, &og_data, 0, sizeof(og_data));
+
+    // Plain Redundancy(2) would have a tie here, and no way to tell which copy is right
+    x[0] ^= 0b0010;
+    int r = er_correct_buffer(x);
+    printf("er_correct_buffer: %d, x[0] = %d\n", r, x[0]);
+    er_free(x);
+
+    END_FUNC;
+}
+
+void typed_redundancy_test(void) {
+
+    START_FUNC;
+
+    struct er_typed_vote vote = {
+        .n_copies = 3,
+        .element = ER_ELEMENT_F64,
+        .mode = ER_VOTE_MEDIAN,
+        .epsilon = 0.0
+    };
+    struct er_policy_list p = {
+        .policy = TypedRedundancy,
+        .policy_data = &vote,
+        .next_policy = NULL
+    };
+
+    double* x = er_malloc(2 * sizeof(double), &p);
+    double og_data[2] = {101.325, -40.0};
+    er_write_buf(x, &og_data, 0, sizeof(og_data));
+
+    // Two of the three copies of the first reading are off, one in each direction, and the
+    // median is the copy that is not
+    unsigned char* copies = (unsigned char*)x;
+    copies[7] ^= 0x40;
+    copies[sizeof(og_data) + 6] ^= 0x02;
+    size_t disagreed[3];
+    int n = er_disagreeing_copies(x, disagreed, 3);
+    printf("er_disagreeing_copies: %d (", n);
+    for (int i = 0; i < n; i++) {
+        printf(" %zu", disagreed[i]);
+    }
+    printf(" ), x[0] = %g\n", x[0]);
+    er_free(x);
+
+    // Votes out of range are rejected
+    vote.epsilon = -1.0;
+    x = er_malloc(2 * sizeof(double), &p);
+    printf("negative epsilon: %p, EINVAL: %d\n", (void*) x, errno == EINVAL);
+    vote.epsilon = 0.0;
+    vote.n_copies = 0;
+    x = er_malloc(2 * sizeof(double), &p);
+    printf("no copies: %p, EINVAL: %d\n", (void*) x, errno == EINVAL);
+    p.policy_data = NULL;
+    x = er_malloc(2 * sizeof(double), &p);
+    printf("no vote: %p, EINVAL: %d\n", (void*) x, errno == EINVAL);
+
+    END_FUNC;
+}
+
+void separated_redundancy_test(void) {
+
+    START_FUNC;
+
+    struct er_separation separation = {
+        .n_copies = 3,
+        .guard_pages = 1
+    };
+    struct er_policy_list p = {
+        .policy = SeparatedRedundancy,
+        .policy_data = &separation,
+        .next_policy = NULL
+    };
+
+    int* x = er_malloc(4 * sizeof(int), &p);
+    int og_data[4] = {1, 2, 3, 4};
+    er_write_buf(x, &og_data, 0, sizeof(og_data));
+
+    // The page that holds the block is wiped out, but the other copies are on pages of their own
+    memset(x, 0xa5, sizeof(og_data));
+    int r = er_correct_buffer(x);
+    printf("er_correct_buffer: %d, x = {%d, %d, %d, %d}\n", r, x[0], x[1], x[2], x[3]);
+    er_free(x);
+
+    END_FUNC;
+}
+
+void bad_pointer_test(void) {
+
+    START_FUNC;
+
+    int* x = er_malloc(16 * sizeof(int), NULL);
+    int og_data[16] = {0};
+    er_write_buf(x, &og_data, 0, sizeof(og_data));
+
+    // A pointer into the middle of a block, and one from another allocator
+    int* y = malloc(16 * sizeof(int));
+    printf("er_free(x + 1): %d\n", er_free(x + 1));
+    printf("er_free(malloc'd): %d\n", er_free(y));
+    free(y);
+
+    // Freed blocks stay marked as freed until their memory is reused
+    int r = er_free(x);
+    int after_free = er_read_buf(x, &og_data, 0, sizeof(int));
+    int double_free = er_free(x);
+    printf("er_free: %d, er_read_buf after free: %d, double free: %d\n", r, after_free, double_free);
+
+    END_FUNC;
+}
+
+int print_block(const struct er_block_info* info, void* ctx) {
+    int* left = ctx;
+    printf("block %llu: length %zu, buffer_size %zu\n",
+           (unsigned long long)info->id, info->length, info->buffer_size);
+    return --*left == 0;
+}
+
+void block_list_test(void) {
+
+    START_FUNC;
+
+    struct er_policy_list p = {
+        .policy = Redundancy,
+        .policy_data = &(int){3},
+        .next_policy = NULL
+    };
+
+    int* x = er_malloc(4 * sizeof(int), NULL);
+    int* y = er_malloc(4 * sizeof(int), &p);
+
+    // The most recent blocks come first, so stopping after two lists just these
+    int left = 2;
+    int r = er_for_each_block(print_block, &left);
+    printf("listed: %d\n", r);
+
+    er_free(x);
+    er_free(y);
+
+    END_FUNC;
+}
+
+void scrubber_test(void) {
+
+    START_FUNC;
+
+    struct er_policy_list p = {
+        .policy = Redundancy,
+        .policy_data = &(int){3},
+        .next_policy = NULL
+    };
+
+    int* x = er_malloc(4 * sizeof(int), &p);
+    int og_data[4] = {1, 2, 3, 4};
+    er_write_buf(x, &og_data, 0, sizeof(og_data));
+
+    // Flip a bit in the data and in the second copy, which is never read
+    x[0] ^= 1 << 3;
+    x[5] ^= 1 << 7;
+
+    struct er_scrub_stats before, after;
+    er_scrubber_stats(&before);
+    int r = er_scrubber_start(10, 4096);
+    while (r == 0) {
+        er_scrubber_stats(&after);
+        if (after.passes > before.passes) {
+            break;
+        }
+        usleep(1000);
+    }
+    er_scrubber_stop();
+
+    printf("er_scrubber_start: %d, x[0] = %d, x[5] = %d\n", r, x[0], x[5]);
+    printf("passes: %llu, corrected at least: %d\n",
+           (unsigned long long)(after.passes - before.passes), after.corrected - before.corrected >= 2);
+    er_free(x);
+
+    END_FUNC;
+}
+
 void rs_and_redundant_test(void) {
 
     START_FUNC;
@@ -159,6 +582,147 @@ void encryption_test(void) {
 
 }
 
+void locked_memory_test(void) {
+
+    START_FUNC;
+
+    struct er_policy_list p = {
+        .policy = Encrypted,
+        .policy_data = &(enum er_lock_mode){ER_LOCK_LOCKED},
+        .next_policy = NULL
+    };
+
+    int og_data[4] = {1, 2, 3, 4};
+    int read_data[4] = {0};
+    int* x = er_malloc(sizeof(og_data), &p);
+    printf("locked er_malloc: %s\n", x ? "ok" : strerror(errno));
+    if (x) {
+        er_write_buf(x, og_data, 0, sizeof(og_data));
+        int r = er_read_buf(x, read_data, 0, sizeof(read_data));
+        printf("er_read_buf: %d, read_data[3] = %d\n", r, read_data[3]);
+        er_free(x);
+    }
+
+    // Anything other than an enum er_lock_mode is refused
+    p.policy_data = &(int){7};
+    errno = 0;
+    x = er_malloc(sizeof(og_data), &p);
+    printf("unknown lock mode: %p, EINVAL: %d\n", (void*)x, errno == EINVAL);
+
+    END_FUNC;
+}
+
+void authenticated_test(void) {
+
+    START_FUNC;
+
+    struct er_policy_list p = {
+        .policy = Authenticated,
+        .policy_data = NULL,
+        .next_policy = NULL
+    };
+
+    struct er_policy_list p2 = {
+        .policy = ReedSolomon,
+        .policy_data = &(int){3},
+        .next_policy = &p
+    };
+
+    int og_data[7];
+    for (int i = 0; i < 7; i++) {
+        og_data[i] = i;
+    }
+    size_t len = 7 * sizeof(int);
+
+    int* x = er_malloc(len, &p2);
+    er_write_buf(x, &og_data, 0, len);
+
+    // A single flip is corrected by Reed-Solomon before the tag is checked
+    x[3] ^= 1 << 30;
+    int recv[7];
+    int r = er_read_buf(x, &recv, 0, len);
+    printf("er_read_buf: %d, recv[3] = %d\n", r, recv[3]);
+
+    // Without error correction, any flip is reported instead of returning wrong data
+    int* y = er_malloc(len, &p);
+    er_write_buf(y, &og_data, 0, len);
+    y[3] ^= 1 << 30;
+    r = er_read_buf(y, &recv, 0, len);
+    printf("er_read_buf: %d (ER_TAG_MISMATCH = %d)\n", r, ER_TAG_MISMATCH);
+
+    er_free(x);
+    er_free(y);
+
+    END_FUNC;
+
+}
+
+/* Hands out a fixed key, or fails if ctx points to a nonzero int */
+int demo_key_provider(void* ctx, unsigned char* key, size_t len) {
+    if (*(int*)ctx) {
+        return 1;
+    }
+    for (size_t i = 0; i < len; i++) {
+        key[i] = (unsigned char)i;
+    }
+    return 0;
+}
+
+void key_rotation_test(void) {
+
+    START_FUNC;
+
+    struct er_policy_list p = {
+        .policy = Encrypted,
+        .policy_data = NULL,
+        .next_policy = NULL
+    };
+
+    struct er_policy_list p2 = {
+        .policy = ReedSolomon,
+        .policy_data = &(int){3},
+        .next_policy = &p
+    };
+
+    int og_data[7];
+    for (int i = 0; i < 7; i++) {
+        og_data[i] = i;
+    }
+    size_t len = 7 * sizeof(int);
+
+    int* x = er_malloc(len, &p2);
+    er_write_buf(x, &og_data, 0, len);
+    printf("x[0] = %d\n", x[0]);
+
+    int r = er_rotate_key();
+    printf("er_rotate_key: %d, x[0] = %d\n", r, x[0]);
+
+    char key[16] = "0123456789abcdef";
+    r = er_set_key(key, sizeof(key));
+    printf("er_set_key: %d, x[0] = %d\n", r, x[0]);
+    r = er_set_key(key, 8);
+    printf("er_set_key: %d (ER_INVALID_KEY = %d)\n", r, ER_INVALID_KEY);
+
+    // The provider stays installed after this function returns, so its context must too
+    static int provider_fails = 0;
+    r = er_register_key_provider(demo_key_provider, &provider_fails);
+    printf("er_register_key_provider: %d, x[0] = %d\n", r, x[0]);
+    r = er_register_key_provider(demo_key_provider, &(int){1});
+    printf("er_register_key_provider: %d (ER_KEY_UNAVAILABLE = %d)\n", r, ER_KEY_UNAVAILABLE);
+    r = er_load_key_file("/nonexistent");
+    printf("er_load_key_file: %d (ER_KEY_UNAVAILABLE = %d)\n", r, ER_KEY_UNAVAILABLE);
+
+    int recv[7];
+    r = er_read_buf(x, &recv, 0, len);
+    for (int i = 0; i < 7; i++) {
+        printf("recv[%d] = %d\n", i, recv[i]);
+    }
+    er_free(x);
+
+    END_FUNC;
+
+}
+
 void combined_test(void) {
 
     START_FUNC;
@@ -361,8 +925,24 @@ int main(void)
     malloc_free_test();
     redundant_test();
     rs_test();
+    secded_test();
+    checksum_test();
+    interleaved_test();
+    unequal_protection_test();
+    data_only_test();
+    linear_code_test();
+    rs_field_test();
+    checked_redundancy_test();
+    typed_redundancy_test();
+    separated_redundancy_test();
+    bad_pointer_test();
+    block_list_test();
+    scrubber_test();
     rs_and_redundant_test();
     encryption_test();
+    authenticated_test();
+    locked_memory_test();
+    key_rotation_test();
     combined_test();
     resilience_test();
     default_test();